@@ -1,6 +1,10 @@
-DELETE FROM urls u WHERE NOT EXISTS (
+-- moderated links are kept so takedowns and their click history survive
+DELETE FROM urls u WHERE u.status = 'active' AND NOT EXISTS (
   SELECT 1 FROM clicks c
   WHERE c.code = u.code
   AND c.clicked_at > NOW() - make_interval(days => $1) 
+) AND NOT EXISTS (
+  SELECT 1 FROM click_rollups_daily r
+  WHERE r.code = u.code
+  AND r.day > DATE(NOW() - make_interval(days => $1))
 );
-
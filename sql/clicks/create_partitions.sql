@@ -0,0 +1 @@
+SELECT create_click_partitions($1);
@@ -0,0 +1 @@
+SELECT drop_expired_click_partitions($1);
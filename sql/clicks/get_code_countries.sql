@@ -0,0 +1,11 @@
+SELECT country, SUM(count)::BIGINT AS count
+FROM (
+  SELECT country, count FROM click_rollups_country_daily
+  WHERE code = $1 AND (NOT is_bot OR $2)
+  UNION ALL
+  SELECT COALESCE(country, ''), 1 FROM clicks
+  WHERE code = $1 AND (NOT is_bot OR $2)
+    AND clicked_at >= (SELECT rolled_up_to FROM rollup_state)
+) merged
+GROUP BY country
+ORDER BY count DESC, country;
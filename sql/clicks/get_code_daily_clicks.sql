@@ -1,5 +1,10 @@
-SELECT DATE(clicked_at) AS date, COUNT(*)  count
-FROM clicks
-WHERE code = ($1)
-GROUP BY DATE(clicked_at)
-ORDER BY DATE(clicked_at);
+SELECT merged.day AS date, SUM(merged.count)::BIGINT AS count, v.visitors AS unique_visitors
+FROM (
+  SELECT day, count FROM click_rollups_daily WHERE code = $1 AND (NOT is_bot OR $2)
+  UNION ALL
+  SELECT DATE(clicked_at), 1 FROM clicks
+  WHERE code = $1 AND (NOT is_bot OR $2) AND clicked_at >= (SELECT rolled_up_to FROM rollup_state)
+) merged
+LEFT JOIN daily_unique_visitors v ON v.code = $1 AND v.day = merged.day
+GROUP BY merged.day, v.visitors
+ORDER BY merged.day;
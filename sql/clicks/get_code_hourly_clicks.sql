@@ -0,0 +1,11 @@
+SELECT hour, SUM(count)::BIGINT AS count
+FROM (
+  SELECT hour, count FROM click_rollups_hourly
+  WHERE code = $1 AND (NOT is_bot OR $2) AND hour >= date_trunc('hour', LOCALTIMESTAMP) - INTERVAL '23 hours'
+  UNION ALL
+  SELECT date_trunc('hour', clicked_at), 1 FROM clicks
+  WHERE code = $1 AND (NOT is_bot OR $2) AND clicked_at >= (SELECT rolled_up_to FROM rollup_state)
+) merged
+WHERE hour >= date_trunc('hour', LOCALTIMESTAMP) - INTERVAL '23 hours'
+GROUP BY hour
+ORDER BY hour;
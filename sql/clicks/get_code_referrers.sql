@@ -0,0 +1,11 @@
+SELECT referrer_host, SUM(count)::BIGINT AS count
+FROM (
+  SELECT referrer_host, count FROM click_rollups_daily
+  WHERE code = $1 AND (NOT is_bot OR $2) AND referrer_host <> ''
+  UNION ALL
+  SELECT referrer_host, 1 FROM clicks
+  WHERE code = $1 AND (NOT is_bot OR $2) AND referrer_host IS NOT NULL
+    AND clicked_at >= (SELECT rolled_up_to FROM rollup_state)
+) merged
+GROUP BY referrer_host
+ORDER BY count DESC, referrer_host;
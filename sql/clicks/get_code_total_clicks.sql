@@ -1 +1,6 @@
-SELECT count(*) FROM clicks where code = ($1); 
+SELECT
+  COALESCE((SELECT SUM(count) FROM click_rollups_daily WHERE code = $1 AND (NOT is_bot OR $2)), 0)::BIGINT
+  + (
+    SELECT COUNT(*) FROM clicks
+    WHERE code = $1 AND (NOT is_bot OR $2) AND clicked_at >= (SELECT rolled_up_to FROM rollup_state)
+  );
@@ -0,0 +1,11 @@
+SELECT variant, SUM(count)::BIGINT AS count
+FROM (
+  SELECT variant, count FROM click_rollups_variant_daily
+  WHERE code = $1 AND (NOT is_bot OR $2)
+  UNION ALL
+  SELECT variant, 1 FROM clicks
+  WHERE code = $1 AND (NOT is_bot OR $2) AND variant IS NOT NULL
+    AND clicked_at >= (SELECT rolled_up_to FROM rollup_state)
+) merged
+GROUP BY variant
+ORDER BY variant;
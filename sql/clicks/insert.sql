@@ -1 +1 @@
-INSERT INTO clicks (code) VALUES ($1);
+INSERT INTO clicks (code, referrer_host, country, variant, is_bot) VALUES ($1, $2, $3, $4, $5);
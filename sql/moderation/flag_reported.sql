@@ -0,0 +1,5 @@
+UPDATE urls SET status = 'flagged'
+WHERE code = $1
+  AND status = 'active'
+  AND (SELECT COUNT(*) FROM abuse_reports r WHERE r.code = $1 AND r.resolved_at IS NULL) >= $2
+RETURNING code;
@@ -0,0 +1,3 @@
+INSERT INTO abuse_reports (code, reason, details, reporter)
+VALUES ($1, $2, $3, $4)
+ON CONFLICT (code, reporter) WHERE resolved_at IS NULL DO NOTHING;
@@ -0,0 +1,13 @@
+SELECT
+  u.code, u.url, u.status, u.threat_detected_at,
+  COUNT(*) AS open_reports,
+  array_agg(DISTINCT r.reason) AS reasons,
+  (array_agg(r.details ORDER BY r.created_at DESC) FILTER (WHERE r.details IS NOT NULL))[1:5] AS details,
+  MIN(r.created_at) AS first_reported_at,
+  MAX(r.created_at) AS last_reported_at
+FROM abuse_reports r
+JOIN urls u ON u.code = r.code
+WHERE r.resolved_at IS NULL
+GROUP BY u.code
+ORDER BY open_reports DESC, last_reported_at DESC
+LIMIT $1 OFFSET $2;
@@ -0,0 +1,10 @@
+-- the first replica to start stores its random salt, every other one reads it back
+WITH inserted AS (
+  INSERT INTO service_secrets (name, value) VALUES ('report_hash_salt', $1)
+  ON CONFLICT (name) DO NOTHING
+  RETURNING value
+)
+SELECT value FROM inserted
+UNION ALL
+SELECT value FROM service_secrets WHERE name = 'report_hash_salt'
+LIMIT 1;
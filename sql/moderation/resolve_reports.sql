@@ -0,0 +1 @@
+UPDATE abuse_reports SET resolved_at = NOW() WHERE code = $1 AND resolved_at IS NULL;
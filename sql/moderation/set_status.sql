@@ -0,0 +1,7 @@
+-- reactivating a link also clears a threat list match, e.g. after a false positive
+UPDATE urls SET
+  status = $2,
+  disabled_reason = $3,
+  threat_detected_at = CASE WHEN $2 = 'active' THEN NULL ELSE threat_detected_at END
+WHERE code = $1
+RETURNING code;
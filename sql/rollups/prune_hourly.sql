@@ -0,0 +1 @@
+DELETE FROM click_rollups_hourly WHERE hour < LOCALTIMESTAMP - make_interval(days => $1);
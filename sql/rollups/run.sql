@@ -0,0 +1,34 @@
+-- the upper bound lags behind so clicks in transactions still running when their hour ends are committed before it is rolled up
+WITH bounds AS (
+  SELECT rolled_up_to AS lower, date_trunc('hour', LOCALTIMESTAMP - INTERVAL '5 minutes') AS upper
+  FROM rollup_state
+  FOR UPDATE
+),
+new_clicks AS (
+  SELECT c.code, date_trunc('hour', c.clicked_at) AS hour, COALESCE(c.referrer_host, '') AS referrer_host, COALESCE(c.country, '') AS country, c.variant, c.is_bot, COUNT(*) AS count
+  FROM clicks c, bounds b
+  WHERE c.clicked_at >= b.lower AND c.clicked_at < b.upper
+  GROUP BY 1, 2, 3, 4, 5, 6
+),
+hourly AS (
+  INSERT INTO click_rollups_hourly (code, hour, referrer_host, is_bot, count)
+  SELECT code, hour, referrer_host, is_bot, SUM(count)::BIGINT FROM new_clicks GROUP BY 1, 2, 3, 4
+  ON CONFLICT (code, hour, referrer_host, is_bot) DO UPDATE SET count = click_rollups_hourly.count + EXCLUDED.count
+),
+daily AS (
+  INSERT INTO click_rollups_daily (code, day, referrer_host, is_bot, count)
+  SELECT code, DATE(hour), referrer_host, is_bot, SUM(count)::BIGINT FROM new_clicks GROUP BY 1, 2, 3, 4
+  ON CONFLICT (code, day, referrer_host, is_bot) DO UPDATE SET count = click_rollups_daily.count + EXCLUDED.count
+),
+countries AS (
+  INSERT INTO click_rollups_country_daily (code, day, country, is_bot, count)
+  SELECT code, DATE(hour), country, is_bot, SUM(count)::BIGINT FROM new_clicks GROUP BY 1, 2, 3, 4
+  ON CONFLICT (code, day, country, is_bot) DO UPDATE SET count = click_rollups_country_daily.count + EXCLUDED.count
+),
+variants AS (
+  INSERT INTO click_rollups_variant_daily (code, day, variant, is_bot, count)
+  SELECT code, DATE(hour), variant, is_bot, SUM(count)::BIGINT FROM new_clicks WHERE variant IS NOT NULL GROUP BY 1, 2, 3, 4
+  ON CONFLICT (code, day, variant, is_bot) DO UPDATE SET count = click_rollups_variant_daily.count + EXCLUDED.count
+)
+UPDATE rollup_state SET rolled_up_to = (SELECT upper FROM bounds)
+RETURNING (SELECT COALESCE(SUM(count), 0)::BIGINT FROM new_clicks);
@@ -1,14 +1,585 @@
 CREATE TABLE IF NOT EXISTS urls (
-  code VARCHAR(6) PRIMARY KEY,
-  url TEXT NOT NULL UNIQUE,
-  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+  code VARCHAR(16) PRIMARY KEY,
+  url TEXT NOT NULL,
+  -- normalized url used to find an existing code for the same destination
+  canonical_url TEXT NOT NULL,
+  -- client supplied namespace (X-Creator-Id), duplicate detection only matches links of the same creator
+  creator_id TEXT,
+  -- whether the link can be handed out again for the same destination, never for links with per-link options
+  dedupe BOOLEAN NOT NULL DEFAULT TRUE,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+  -- NULL falls back to the service wide DEFAULT_REDIRECT_TYPE
+  redirect_type SMALLINT CHECK (redirect_type IN (301, 302, 307, 308)),
+  forward_query BOOLEAN NOT NULL DEFAULT FALSE,
+  forward_path BOOLEAN NOT NULL DEFAULT FALSE,
+  query_conflict TEXT NOT NULL DEFAULT 'destination' CHECK (query_conflict IN ('destination', 'incoming', 'both')),
+  -- utm_campaign of the destination, used to group analytics by campaign
+  utm_campaign TEXT,
+  -- activation window, the link 404s before not_before and is gone from not_after
+  not_before TIMESTAMPTZ,
+  not_after TIMESTAMPTZ,
+  -- argon2 hash for password protected links
+  password_hash TEXT,
+  -- single use links stop working once consumed_at is set by their first redirect
+  single_use BOOLEAN NOT NULL DEFAULT FALSE,
+  consumed_at TIMESTAMPTZ,
+  -- show a confirmation page with the destination instead of redirecting
+  interstitial BOOLEAN NOT NULL DEFAULT FALSE,
+  -- set when a destination matched the threat list, the link then serves a warning page
+  threat_detected_at TIMESTAMPTZ,
+  -- set when a destination matched only a hash prefix, the link is then flagged for review once
+  threat_flagged_at TIMESTAMPTZ,
+  -- moderation state, disabled links stop redirecting but keep their clicks
+  status TEXT NOT NULL DEFAULT 'active' CHECK (status IN ('active', 'disabled', 'flagged')),
+  disabled_reason TEXT CHECK (disabled_reason IN ('abuse', 'legal')),
+  CONSTRAINT urls_window_check CHECK (not_before < not_after),
+  CONSTRAINT urls_disabled_status_check CHECK ((status = 'disabled') = (disabled_reason IS NOT NULL))
 );
 
-CREATE TABLE IF NOT EXISTS clicks (
+-- bring a urls table created by an earlier version up to date, existing links keep behaving
+-- as plain links. Their canonical url is the stored url, so only exact repeats find them.
+ALTER TABLE urls DROP CONSTRAINT IF EXISTS urls_url_key;
+ALTER TABLE urls
+  ADD COLUMN IF NOT EXISTS canonical_url TEXT,
+  ADD COLUMN IF NOT EXISTS creator_id TEXT,
+  ADD COLUMN IF NOT EXISTS dedupe BOOLEAN NOT NULL DEFAULT TRUE,
+  ADD COLUMN IF NOT EXISTS redirect_type SMALLINT CHECK (redirect_type IN (301, 302, 307, 308)),
+  ADD COLUMN IF NOT EXISTS forward_query BOOLEAN NOT NULL DEFAULT FALSE,
+  ADD COLUMN IF NOT EXISTS forward_path BOOLEAN NOT NULL DEFAULT FALSE,
+  ADD COLUMN IF NOT EXISTS query_conflict TEXT NOT NULL DEFAULT 'destination' CHECK (query_conflict IN ('destination', 'incoming', 'both')),
+  ADD COLUMN IF NOT EXISTS utm_campaign TEXT,
+  ADD COLUMN IF NOT EXISTS not_before TIMESTAMPTZ,
+  ADD COLUMN IF NOT EXISTS not_after TIMESTAMPTZ,
+  ADD COLUMN IF NOT EXISTS password_hash TEXT,
+  ADD COLUMN IF NOT EXISTS single_use BOOLEAN NOT NULL DEFAULT FALSE,
+  ADD COLUMN IF NOT EXISTS consumed_at TIMESTAMPTZ,
+  ADD COLUMN IF NOT EXISTS interstitial BOOLEAN NOT NULL DEFAULT FALSE,
+  ADD COLUMN IF NOT EXISTS threat_detected_at TIMESTAMPTZ,
+  ADD COLUMN IF NOT EXISTS threat_flagged_at TIMESTAMPTZ,
+  ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'active' CHECK (status IN ('active', 'disabled', 'flagged')),
+  ADD COLUMN IF NOT EXISTS disabled_reason TEXT CHECK (disabled_reason IN ('abuse', 'legal'));
+
+UPDATE urls SET canonical_url = url WHERE canonical_url IS NULL;
+ALTER TABLE urls ALTER COLUMN canonical_url SET NOT NULL;
+
+DO $$
+BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conrelid = 'urls'::regclass AND conname = 'urls_window_check') THEN
+    ALTER TABLE urls ADD CONSTRAINT urls_window_check CHECK (not_before < not_after);
+  END IF;
+  IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conrelid = 'urls'::regclass AND conname = 'urls_disabled_status_check') THEN
+    ALTER TABLE urls ADD CONSTRAINT urls_disabled_status_check CHECK ((status = 'disabled') = (disabled_reason IS NOT NULL));
+  END IF;
+END;
+$$;
+
+CREATE INDEX IF NOT EXISTS idx_urls_utm_campaign ON urls(utm_campaign) WHERE utm_campaign IS NOT NULL;
+
+-- numbers behind CODE_STRATEGY=counter, turned into codes by a keyed permutation
+CREATE SEQUENCE IF NOT EXISTS url_code_numbers MINVALUE 0 START 0;
+
+-- per-link targeting rules, evaluated in position order with the first match winning
+CREATE TABLE IF NOT EXISTS link_rules (
+  code VARCHAR(16) REFERENCES urls(code) ON DELETE CASCADE,
+  position INT NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('platform', 'country', 'language')),
+  value TEXT NOT NULL,
+  url TEXT NOT NULL,
+  PRIMARY KEY (code, position)
+);
+
+-- weighted A/B destinations, one is drawn per visitor when no targeting rule matches
+CREATE TABLE IF NOT EXISTS link_variants (
+  code VARCHAR(16) REFERENCES urls(code) ON DELETE CASCADE,
+  position INT NOT NULL,
+  label TEXT NOT NULL,
+  url TEXT NOT NULL,
+  weight INT NOT NULL CHECK (weight > 0),
+  PRIMARY KEY (code, position),
+  UNIQUE (code, label)
+);
+
+-- destinations replacing urls.url from starts_at until the next entry starts
+CREATE TABLE IF NOT EXISTS link_schedules (
+  code VARCHAR(16) REFERENCES urls(code) ON DELETE CASCADE,
+  starts_at TIMESTAMPTZ NOT NULL,
+  url TEXT NOT NULL,
+  PRIMARY KEY (code, starts_at)
+);
+
+-- links with their own options behave differently from a plain link to the same destination,
+-- so links created with them before they were excluded from duplicate detection stop taking part
+UPDATE urls SET dedupe = FALSE
+WHERE dedupe AND (
+  redirect_type IS NOT NULL OR forward_query OR forward_path OR query_conflict <> 'destination'
+  OR not_before IS NOT NULL OR not_after IS NOT NULL OR password_hash IS NOT NULL
+  OR single_use OR interstitial
+  OR EXISTS (SELECT 1 FROM link_rules r WHERE r.code = urls.code)
+  OR EXISTS (SELECT 1 FROM link_variants v WHERE v.code = urls.code)
+  OR EXISTS (SELECT 1 FROM link_schedules s WHERE s.code = urls.code)
+);
+
+-- each creator has at most one deduplicated link per destination, links without a creator share one
+-- namespace. Time windowed links are never deduplicated, scheduled ones already have dedupe unset.
+DROP INDEX IF EXISTS idx_urls_url_public;
+DROP INDEX IF EXISTS idx_urls_url_shared;
+DROP INDEX IF EXISTS idx_urls_canonical_shared;
+DROP INDEX IF EXISTS idx_urls_creator_canonical;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_urls_dedupe ON urls((COALESCE(creator_id, '')), canonical_url)
+  WHERE dedupe AND not_before IS NULL AND not_after IS NULL;
+
+-- abuse reports from visitors, resolved when a moderator activates or disables the link
+CREATE TABLE IF NOT EXISTS abuse_reports (
   id BIGSERIAL PRIMARY KEY,
-  code VARCHAR(6) REFERENCES urls(code) ON DELETE CASCADE,
-  clicked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+  code VARCHAR(16) NOT NULL REFERENCES urls(code) ON DELETE CASCADE,
+  reason TEXT NOT NULL CHECK (reason IN ('phishing', 'malware', 'spam', 'illegal', 'other')),
+  details TEXT,
+  -- salted hash of the reporter's IP, so one visitor counts once towards flagging a link
+  reporter TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  resolved_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_abuse_reports_open ON abuse_reports(code, reporter) WHERE resolved_at IS NULL;
+
+-- random values shared by all replicas when no secret is configured, e.g. the reporter hash salt
+CREATE TABLE IF NOT EXISTS service_secrets (
+  name TEXT PRIMARY KEY,
+  value TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS utm_templates (
+  name TEXT PRIMARY KEY,
+  source TEXT,
+  medium TEXT,
+  campaign TEXT,
+  term TEXT,
+  content TEXT,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
 );
 
+-- monthly partitions (clicks_YYYY_MM) are created ahead of time and dropped once expired
+-- clicks used to be a plain table, move it aside so its rows can be copied into the partitioned table at the end
+DO $$
+BEGIN
+  IF EXISTS (SELECT 1 FROM pg_class WHERE oid = to_regclass('clicks') AND relkind = 'r') THEN
+    ALTER TABLE clicks RENAME TO clicks_unpartitioned;
+    ALTER INDEX IF EXISTS clicks_pkey RENAME TO clicks_unpartitioned_pkey;
+    ALTER INDEX IF EXISTS idx_clicks_code_date RENAME TO idx_clicks_unpartitioned_code_date;
+    ALTER SEQUENCE IF EXISTS clicks_id_seq RENAME TO clicks_unpartitioned_id_seq;
+  END IF;
+END;
+$$;
+
+CREATE TABLE IF NOT EXISTS clicks (
+  id BIGSERIAL,
+  code VARCHAR(16) REFERENCES urls(code) ON DELETE CASCADE,
+  clicked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  referrer_host TEXT,
+  -- ISO 3166-1 alpha-2 code from the GeoIP database, NULL when unresolved
+  country TEXT,
+  -- label of the A/B variant served, NULL for links without variants
+  variant TEXT,
+  is_bot BOOLEAN NOT NULL DEFAULT FALSE,
+  PRIMARY KEY (id, clicked_at)
+) PARTITION BY RANGE (clicked_at);
+
+-- catches clicks for months without a partition yet, create_click_partitions moves them out again
+CREATE TABLE IF NOT EXISTS clicks_default PARTITION OF clicks DEFAULT;
+
 -- speeds up queries that filter or join on the code field in the clicks table
-CREATE INDEX idx_clicks_code_date ON clicks(code, clicked_at);
+CREATE INDEX IF NOT EXISTS idx_clicks_code_date ON clicks(code, clicked_at);
+
+-- global counters kept in sync by the triggers below so /stats never has to count rows. Each
+-- counter is split over counter_shard() rows that /stats sums, so concurrent redirects don't
+-- all wait on the lock of a single row.
+CREATE TABLE IF NOT EXISTS counters (
+  name TEXT NOT NULL,
+  shard SMALLINT NOT NULL DEFAULT 0,
+  value BIGINT NOT NULL DEFAULT 0,
+  PRIMARY KEY (name, shard)
+);
+
+-- counters from before sharding were keyed by name alone and become shard 0
+ALTER TABLE counters ADD COLUMN IF NOT EXISTS shard SMALLINT NOT NULL DEFAULT 0;
+
+DO $$
+BEGIN
+  IF NOT EXISTS (
+    SELECT 1 FROM information_schema.key_column_usage
+    WHERE table_schema = current_schema() AND table_name = 'counters'
+      AND constraint_name = 'counters_pkey' AND column_name = 'shard'
+  ) THEN
+    ALTER TABLE counters DROP CONSTRAINT counters_pkey;
+    ALTER TABLE counters ADD PRIMARY KEY (name, shard);
+  END IF;
+END;
+$$;
+
+-- the shard a counter update goes to, changing the shard count needs no migration
+CREATE OR REPLACE FUNCTION counter_shard() RETURNS SMALLINT AS $$
+  SELECT floor(random() * 16)::SMALLINT;
+$$ LANGUAGE sql VOLATILE;
+
+INSERT INTO counters (name, shard, value)
+VALUES
+  ('total_urls', 0, (SELECT COUNT(*) FROM urls)),
+  ('total_clicks', 0, (SELECT COUNT(*) FROM clicks WHERE NOT is_bot)),
+  ('total_bot_clicks', 0, (SELECT COUNT(*) FROM clicks WHERE is_bot))
+ON CONFLICT (name, shard) DO NOTHING;
+
+-- adds to one shard of a counter, creating the shard's row on its first use
+CREATE OR REPLACE FUNCTION counters_add(counter TEXT, delta BIGINT) RETURNS VOID AS $$
+  INSERT INTO counters (name, shard, value)
+  VALUES (counter, counter_shard(), delta)
+  ON CONFLICT (name, shard) DO UPDATE SET value = counters.value + EXCLUDED.value;
+$$ LANGUAGE sql;
+
+-- statement level so bulk deletes (stale url cleanup, cascades) update a counter once
+CREATE OR REPLACE FUNCTION counters_add_inserted() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM counters_add(TG_ARGV[0], (SELECT COUNT(*) FROM inserted_rows));
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION counters_subtract_deleted() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM counters_add(TG_ARGV[0], -(SELECT COUNT(*) FROM deleted_rows));
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER urls_count_insert
+  AFTER INSERT ON urls REFERENCING NEW TABLE AS inserted_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION counters_add_inserted('total_urls');
+
+CREATE OR REPLACE TRIGGER urls_count_delete
+  AFTER DELETE ON urls REFERENCING OLD TABLE AS deleted_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION counters_subtract_deleted('total_urls');
+
+-- bot clicks are counted separately so /stats can include or exclude them
+CREATE OR REPLACE FUNCTION clicks_counters_add_inserted() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM counters_add(CASE WHEN is_bot THEN 'total_bot_clicks' ELSE 'total_clicks' END, COUNT(*))
+  FROM inserted_rows
+  GROUP BY is_bot;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION clicks_counters_subtract_deleted() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM counters_add(CASE WHEN is_bot THEN 'total_bot_clicks' ELSE 'total_clicks' END, -COUNT(*))
+  FROM deleted_rows
+  GROUP BY is_bot;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- dropping an expired click partition fires no delete triggers, so retention never changes the totals
+CREATE OR REPLACE TRIGGER clicks_count_insert
+  AFTER INSERT ON clicks REFERENCING NEW TABLE AS inserted_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION clicks_counters_add_inserted();
+
+CREATE OR REPLACE TRIGGER clicks_count_delete
+  AFTER DELETE ON clicks REFERENCING OLD TABLE AS deleted_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION clicks_counters_subtract_deleted();
+
+-- length random and hashed codes grew to after repeated collisions, kept across restarts and replicas
+CREATE TABLE IF NOT EXISTS code_length_state (
+  id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+  length SMALLINT NOT NULL
+);
+
+-- links per code length, kept in sync like the counters above to tell how full each length's code space is
+CREATE TABLE IF NOT EXISTS code_lengths (
+  length SMALLINT PRIMARY KEY,
+  urls BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO code_lengths (length, urls)
+SELECT length(code), COUNT(*) FROM urls GROUP BY 1
+ON CONFLICT (length) DO NOTHING;
+
+CREATE OR REPLACE FUNCTION code_lengths_add_inserted() RETURNS TRIGGER AS $$
+BEGIN
+  INSERT INTO code_lengths (length, urls)
+  SELECT length(code), COUNT(*) FROM inserted_rows GROUP BY 1
+  ON CONFLICT (length) DO UPDATE SET urls = code_lengths.urls + EXCLUDED.urls;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION code_lengths_subtract_deleted() RETURNS TRIGGER AS $$
+BEGIN
+  UPDATE code_lengths SET urls = urls - deleted.count
+  FROM (SELECT length(code) AS length, COUNT(*) AS count FROM deleted_rows GROUP BY 1) deleted
+  WHERE code_lengths.length = deleted.length;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER urls_code_lengths_insert
+  AFTER INSERT ON urls REFERENCING NEW TABLE AS inserted_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION code_lengths_add_inserted();
+
+CREATE OR REPLACE TRIGGER urls_code_lengths_delete
+  AFTER DELETE ON urls REFERENCING OLD TABLE AS deleted_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION code_lengths_subtract_deleted();
+
+-- aggregated clicks produced by the rollup task; stats read these instead of raw clicks
+CREATE TABLE IF NOT EXISTS click_rollups_hourly (
+  code VARCHAR(16) REFERENCES urls(code) ON DELETE CASCADE,
+  hour TIMESTAMP NOT NULL,
+  referrer_host TEXT NOT NULL DEFAULT '',
+  is_bot BOOLEAN NOT NULL DEFAULT FALSE,
+  count BIGINT NOT NULL,
+  PRIMARY KEY (code, hour, referrer_host, is_bot)
+);
+
+CREATE TABLE IF NOT EXISTS click_rollups_daily (
+  code VARCHAR(16) REFERENCES urls(code) ON DELETE CASCADE,
+  day DATE NOT NULL,
+  referrer_host TEXT NOT NULL DEFAULT '',
+  is_bot BOOLEAN NOT NULL DEFAULT FALSE,
+  count BIGINT NOT NULL,
+  PRIMARY KEY (code, day, referrer_host, is_bot)
+);
+
+-- kept apart from click_rollups_daily so referrers, countries and variants don't multiply each other's rows
+CREATE TABLE IF NOT EXISTS click_rollups_country_daily (
+  code VARCHAR(16) REFERENCES urls(code) ON DELETE CASCADE,
+  day DATE NOT NULL,
+  country TEXT NOT NULL DEFAULT '',
+  is_bot BOOLEAN NOT NULL DEFAULT FALSE,
+  count BIGINT NOT NULL,
+  PRIMARY KEY (code, day, country, is_bot)
+);
+
+CREATE TABLE IF NOT EXISTS click_rollups_variant_daily (
+  code VARCHAR(16) REFERENCES urls(code) ON DELETE CASCADE,
+  day DATE NOT NULL,
+  variant TEXT NOT NULL,
+  is_bot BOOLEAN NOT NULL DEFAULT FALSE,
+  count BIGINT NOT NULL,
+  PRIMARY KEY (code, day, variant, is_bot)
+);
+
+-- unique visitor estimates persisted nightly from the redis hyperloglogs
+CREATE TABLE IF NOT EXISTS daily_unique_visitors (
+  code VARCHAR(16) REFERENCES urls(code) ON DELETE CASCADE,
+  day DATE NOT NULL,
+  visitors BIGINT NOT NULL,
+  PRIMARY KEY (code, day)
+);
+
+-- raw clicks before rolled_up_to are already reflected in the rollup tables
+CREATE TABLE IF NOT EXISTS rollup_state (
+  id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+  rolled_up_to TIMESTAMP NOT NULL
+);
+
+INSERT INTO rollup_state (rolled_up_to) VALUES ('-infinity') ON CONFLICT (id) DO NOTHING;
+
+-- links and clicks per utm_campaign, kept in sync and sharded like the counters above so /stats
+-- never groups urls or clicks and a busy campaign doesn't serialize its redirects
+CREATE TABLE IF NOT EXISTS campaign_counters (
+  campaign TEXT NOT NULL,
+  shard SMALLINT NOT NULL DEFAULT 0,
+  urls BIGINT NOT NULL DEFAULT 0,
+  clicks BIGINT NOT NULL DEFAULT 0,
+  bot_clicks BIGINT NOT NULL DEFAULT 0,
+  PRIMARY KEY (campaign, shard)
+);
+
+ALTER TABLE campaign_counters ADD COLUMN IF NOT EXISTS shard SMALLINT NOT NULL DEFAULT 0;
+
+DO $$
+BEGIN
+  IF NOT EXISTS (
+    SELECT 1 FROM information_schema.key_column_usage
+    WHERE table_schema = current_schema() AND table_name = 'campaign_counters'
+      AND constraint_name = 'campaign_counters_pkey' AND column_name = 'shard'
+  ) THEN
+    ALTER TABLE campaign_counters DROP CONSTRAINT campaign_counters_pkey;
+    ALTER TABLE campaign_counters ADD PRIMARY KEY (campaign, shard);
+  END IF;
+END;
+$$;
+
+-- clicks of a single link, from the rollups and the raw clicks not rolled up yet
+CREATE OR REPLACE FUNCTION link_click_totals(link_code TEXT, OUT clicks BIGINT, OUT bot_clicks BIGINT) AS $$
+  SELECT COALESCE(SUM(count) FILTER (WHERE NOT is_bot), 0)::BIGINT,
+         COALESCE(SUM(count) FILTER (WHERE is_bot), 0)::BIGINT
+  FROM (
+    SELECT count, is_bot FROM click_rollups_daily WHERE code = link_code
+    UNION ALL
+    SELECT 1, is_bot FROM clicks
+    WHERE code = link_code AND clicked_at >= (SELECT rolled_up_to FROM rollup_state)
+  ) merged;
+$$ LANGUAGE sql STABLE;
+
+-- seeded only while the table is empty, since a campaign's counts may sit in any shard
+INSERT INTO campaign_counters (campaign, urls, clicks, bot_clicks)
+SELECT u.utm_campaign, COUNT(*), SUM(t.clicks), SUM(t.bot_clicks)
+FROM urls u CROSS JOIN LATERAL link_click_totals(u.code) t
+WHERE u.utm_campaign IS NOT NULL AND NOT EXISTS (SELECT 1 FROM campaign_counters)
+GROUP BY 1;
+
+-- adds to one shard of each campaign's counters, creating the shard's row on its first use
+CREATE OR REPLACE FUNCTION campaign_counters_add(counter TEXT, url_delta BIGINT, click_delta BIGINT, bot_click_delta BIGINT) RETURNS VOID AS $$
+  INSERT INTO campaign_counters (campaign, shard, urls, clicks, bot_clicks)
+  VALUES (counter, counter_shard(), url_delta, click_delta, bot_click_delta)
+  ON CONFLICT (campaign, shard) DO UPDATE SET
+    urls = campaign_counters.urls + EXCLUDED.urls,
+    clicks = campaign_counters.clicks + EXCLUDED.clicks,
+    bot_clicks = campaign_counters.bot_clicks + EXCLUDED.bot_clicks;
+$$ LANGUAGE sql;
+
+CREATE OR REPLACE FUNCTION campaign_counters_add_inserted_urls() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM campaign_counters_add(utm_campaign, COUNT(*), 0, 0)
+  FROM inserted_rows
+  WHERE utm_campaign IS NOT NULL
+  GROUP BY utm_campaign;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- row level and before the delete, since the cascade removes the link's clicks and rollups with it
+CREATE OR REPLACE FUNCTION campaign_counters_subtract_deleted_url() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM campaign_counters_add(OLD.utm_campaign, -1, -t.clicks, -t.bot_clicks)
+  FROM link_click_totals(OLD.code) t;
+  RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION campaign_counters_add_inserted_clicks() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM campaign_counters_add(u.utm_campaign, 0, COUNT(*) FILTER (WHERE NOT r.is_bot), COUNT(*) FILTER (WHERE r.is_bot))
+  FROM inserted_rows r
+  JOIN urls u ON u.code = r.code
+  WHERE u.utm_campaign IS NOT NULL
+  GROUP BY u.utm_campaign;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER urls_campaign_counters_insert
+  AFTER INSERT ON urls REFERENCING NEW TABLE AS inserted_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION campaign_counters_add_inserted_urls();
+
+CREATE OR REPLACE TRIGGER urls_campaign_counters_delete
+  BEFORE DELETE ON urls
+  FOR EACH ROW WHEN (OLD.utm_campaign IS NOT NULL) EXECUTE FUNCTION campaign_counters_subtract_deleted_url();
+
+CREATE OR REPLACE TRIGGER clicks_campaign_counters_insert
+  AFTER INSERT ON clicks REFERENCING NEW TABLE AS inserted_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION campaign_counters_add_inserted_clicks();
+
+CREATE OR REPLACE FUNCTION create_click_partitions(months_ahead INT) RETURNS INT AS $$
+DECLARE
+  month_start DATE;
+  partition_name TEXT;
+  created INT := 0;
+BEGIN
+  -- upcoming months, plus months whose clicks landed in the default partition
+  FOR month_start IN
+    SELECT (date_trunc('month', LOCALTIMESTAMP) + make_interval(months => i))::DATE FROM generate_series(0, months_ahead) i
+    UNION
+    SELECT DISTINCT date_trunc('month', clicked_at)::DATE FROM clicks_default
+  LOOP
+    partition_name := 'clicks_' || to_char(month_start, 'YYYY_MM');
+
+    IF to_regclass(partition_name) IS NULL THEN
+      -- a partition can't be added while the default partition holds rows of its range
+      EXECUTE format('CREATE TABLE %I (LIKE clicks INCLUDING DEFAULTS)', partition_name);
+      EXECUTE format(
+        'WITH moved AS (DELETE FROM clicks_default WHERE clicked_at >= %L AND clicked_at < %L RETURNING *) INSERT INTO %I SELECT * FROM moved',
+        month_start, (month_start + INTERVAL '1 month')::DATE, partition_name
+      );
+      EXECUTE format(
+        'ALTER TABLE clicks ATTACH PARTITION %I FOR VALUES FROM (%L) TO (%L)',
+        partition_name, month_start, (month_start + INTERVAL '1 month')::DATE
+      );
+      created := created + 1;
+    END IF;
+  END LOOP;
+
+  RETURN created;
+END;
+$$ LANGUAGE plpgsql;
+
+-- a partition is only dropped once all of its clicks are past retention and already rolled up
+CREATE OR REPLACE FUNCTION drop_expired_click_partitions(retention_days INT) RETURNS INT AS $$
+DECLARE
+  cutoff TIMESTAMP := LEAST(
+    LOCALTIMESTAMP - make_interval(days => retention_days),
+    (SELECT rolled_up_to FROM rollup_state)
+  );
+  partition_name TEXT;
+  dropped INT := 0;
+BEGIN
+  FOR partition_name IN
+    SELECT c.relname
+    FROM pg_inherits i
+    JOIN pg_class c ON c.oid = i.inhrelid
+    WHERE i.inhparent = 'clicks'::REGCLASS
+      AND c.relname ~ '^clicks_[0-9]{4}_[0-9]{2}$'
+  LOOP
+    IF to_date(substring(partition_name FROM 8), 'YYYY_MM') + INTERVAL '1 month' <= cutoff THEN
+      EXECUTE format('DROP TABLE %I', partition_name);
+      dropped := dropped + 1;
+    END IF;
+  END LOOP;
+
+  RETURN dropped;
+END;
+$$ LANGUAGE plpgsql;
+
+-- codes used to be 6 characters, widening a varchar is a catalog only change
+DO $$
+DECLARE
+  tbl TEXT;
+BEGIN
+  FOREACH tbl IN ARRAY ARRAY[
+    'urls', 'link_rules', 'link_variants', 'link_schedules', 'abuse_reports', 'clicks',
+    'click_rollups_hourly', 'click_rollups_daily', 'click_rollups_country_daily',
+    'click_rollups_variant_daily', 'daily_unique_visitors'
+  ] LOOP
+    EXECUTE format('ALTER TABLE %I ALTER COLUMN code TYPE VARCHAR(16)', tbl);
+  END LOOP;
+END;
+$$;
+
+SELECT create_click_partitions(3);
+
+-- copy the clicks of a plain clicks table moved aside above, then recount since the counters
+-- may or may not have included them
+DO $$
+DECLARE
+  details TEXT;
+BEGIN
+  IF to_regclass('clicks_unpartitioned') IS NOT NULL THEN
+    -- carry over whichever click details the old table already recorded
+    SELECT string_agg(', ' || quote_ident(column_name), '') INTO details
+    FROM information_schema.columns
+    WHERE table_schema = current_schema() AND table_name = 'clicks_unpartitioned'
+      AND column_name IN ('referrer_host', 'country', 'variant', 'is_bot');
+    EXECUTE format(
+      'INSERT INTO clicks (id, code, clicked_at%1$s) SELECT id, code, COALESCE(clicked_at, CURRENT_TIMESTAMP)%1$s FROM clicks_unpartitioned',
+      COALESCE(details, '')
+    );
+    PERFORM setval('clicks_id_seq', GREATEST((SELECT MAX(id) FROM clicks), 1));
+    DELETE FROM counters WHERE name IN ('total_clicks', 'total_bot_clicks');
+    INSERT INTO counters (name, value)
+    VALUES
+      ('total_clicks', (SELECT COUNT(*) FROM clicks WHERE NOT is_bot)),
+      ('total_bot_clicks', (SELECT COUNT(*) FROM clicks WHERE is_bot));
+    DROP TABLE clicks_unpartitioned;
+    PERFORM create_click_partitions(3);
+  END IF;
+END;
+$$;
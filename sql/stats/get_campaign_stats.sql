@@ -0,0 +1,6 @@
+SELECT campaign, SUM(urls)::BIGINT AS total_urls, SUM(clicks + CASE WHEN $1 THEN bot_clicks ELSE 0 END)::BIGINT AS total_clicks
+FROM campaign_counters
+GROUP BY campaign
+HAVING SUM(urls) > 0
+ORDER BY total_clicks DESC, campaign
+LIMIT $2;
@@ -0,0 +1 @@
+SELECT length::INT, urls FROM code_lengths WHERE urls > 0 ORDER BY length;
@@ -1,3 +1,5 @@
 SELECT
-  (SELECT COUNT(*) FROM urls) as total_urls,
-  (SELECT COUNT(*) FROM clicks) as total_clicks;
+  COALESCE(SUM(value) FILTER (WHERE name = 'total_urls'), 0)::BIGINT as total_urls,
+  COALESCE(SUM(value) FILTER (WHERE name = 'total_clicks'), 0)::BIGINT as total_clicks,
+  COALESCE(SUM(value) FILTER (WHERE name = 'total_bot_clicks'), 0)::BIGINT as total_bot_clicks
+FROM counters;
@@ -0,0 +1 @@
+UPDATE urls SET consumed_at = NOW() WHERE code = $1 AND single_use AND consumed_at IS NULL RETURNING code;
@@ -0,0 +1 @@
+SELECT DISTINCT c.code FROM unnest($1::TEXT[]) AS c(code) WHERE NOT EXISTS (SELECT 1 FROM urls WHERE urls.code = c.code);
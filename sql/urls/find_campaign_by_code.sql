@@ -0,0 +1 @@
+SELECT utm_campaign FROM urls WHERE code = $1;
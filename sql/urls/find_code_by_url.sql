@@ -1 +1,3 @@
-SELECT code FROM urls WHERE url = $1;
+SELECT code, status FROM urls
+WHERE COALESCE(creator_id, '') = COALESCE($2, '') AND canonical_url = $1
+  AND dedupe AND not_before IS NULL AND not_after IS NULL;
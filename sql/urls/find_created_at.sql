@@ -0,0 +1 @@
+SELECT created_at FROM urls WHERE code = $1;
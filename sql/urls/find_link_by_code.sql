@@ -0,0 +1,22 @@
+SELECT
+  u.url, u.redirect_type, u.forward_query, u.forward_path, u.query_conflict,
+  COALESCE(
+    (SELECT json_agg(json_build_object('kind', r.kind, 'value', r.value, 'url', r.url) ORDER BY r.position)
+     FROM link_rules r WHERE r.code = u.code),
+    '[]'
+  ) AS rules,
+  COALESCE(
+    (SELECT json_agg(json_build_object('label', v.label, 'url', v.url, 'weight', v.weight) ORDER BY v.position)
+     FROM link_variants v WHERE v.code = u.code),
+    '[]'
+  ) AS variants,
+  u.not_before, u.not_after,
+  COALESCE(
+    (SELECT json_agg(json_build_object('starts_at', s.starts_at, 'url', s.url) ORDER BY s.starts_at)
+     FROM link_schedules s WHERE s.code = u.code),
+    '[]'
+  ) AS schedule,
+  u.password_hash, u.single_use, u.consumed_at, u.interstitial,
+  u.threat_detected_at, u.status, u.disabled_reason
+FROM urls u
+WHERE u.code = $1;
@@ -1 +0,0 @@
-SELECT url from urls where code = $1;
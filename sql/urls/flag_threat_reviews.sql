@@ -0,0 +1,14 @@
+-- a hash prefix alone may be a false positive, so the link is flagged and queued for review
+-- like a reported link, once per link so a reviewed link isn't flagged again
+WITH flagged AS (
+  UPDATE urls SET
+    threat_flagged_at = NOW(),
+    status = CASE WHEN status = 'active' THEN 'flagged' ELSE status END
+  WHERE code = ANY($1) AND threat_flagged_at IS NULL
+  RETURNING code
+), reported AS (
+  INSERT INTO abuse_reports (code, reason, details, reporter)
+  SELECT code, 'other', 'Destination matches a threat list hash prefix', 'threat-list' FROM flagged
+  ON CONFLICT DO NOTHING
+)
+SELECT code FROM flagged;
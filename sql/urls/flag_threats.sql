@@ -0,0 +1 @@
+UPDATE urls SET threat_detected_at = NOW() WHERE code = ANY($1) AND threat_detected_at IS NULL RETURNING code;
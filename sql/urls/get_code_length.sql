@@ -0,0 +1 @@
+SELECT length::INT FROM code_length_state;
@@ -0,0 +1,3 @@
+-- replicas may grow codes concurrently, the stored length only ever increases
+INSERT INTO code_length_state (length) VALUES ($1)
+ON CONFLICT (id) DO UPDATE SET length = GREATEST(code_length_state.length, EXCLUDED.length);
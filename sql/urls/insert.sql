@@ -1 +1,18 @@
-INSERT INTO urls (code, url) VALUES ($1, $2);
+WITH link AS (
+  INSERT INTO urls (code, url, canonical_url, creator_id, dedupe, redirect_type, forward_query, forward_path, query_conflict, utm_campaign, not_before, not_after, password_hash, single_use, interstitial)
+  VALUES ($1, $2, $21, $22, $23, $3, $4, $5, $6, $7, $14, $15, $18, $19, $20)
+  RETURNING code
+),
+rules AS (
+  INSERT INTO link_rules (code, position, kind, value, url)
+  SELECT link.code, rule.position, rule.kind, rule.value, rule.url
+  FROM link, UNNEST($8::TEXT[], $9::TEXT[], $10::TEXT[]) WITH ORDINALITY AS rule(kind, value, url, position)
+),
+variants AS (
+  INSERT INTO link_variants (code, position, label, url, weight)
+  SELECT link.code, variant.position, variant.label, variant.url, variant.weight
+  FROM link, UNNEST($11::TEXT[], $12::TEXT[], $13::INT[]) WITH ORDINALITY AS variant(label, url, weight, position)
+)
+INSERT INTO link_schedules (code, starts_at, url)
+SELECT link.code, entry.starts_at, entry.url
+FROM link, UNNEST($16::TIMESTAMPTZ[], $17::TEXT[]) AS entry(starts_at, url);
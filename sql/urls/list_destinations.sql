@@ -0,0 +1,10 @@
+SELECT
+  u.code,
+  ARRAY[u.url]
+    || ARRAY(SELECT r.url FROM link_rules r WHERE r.code = u.code)
+    || ARRAY(SELECT v.url FROM link_variants v WHERE v.code = u.code)
+    || ARRAY(SELECT s.url FROM link_schedules s WHERE s.code = u.code) AS destinations
+FROM urls u
+WHERE u.code > $1 AND u.threat_detected_at IS NULL
+ORDER BY u.code
+LIMIT $2;
@@ -0,0 +1 @@
+SELECT nextval('url_code_numbers');
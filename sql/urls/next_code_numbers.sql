@@ -0,0 +1 @@
+SELECT nextval('url_code_numbers') FROM generate_series(1, $1);
@@ -0,0 +1 @@
+SELECT CASE WHEN is_called THEN last_value + 1 ELSE last_value END FROM url_code_numbers;
@@ -0,0 +1 @@
+SELECT name, source, medium, campaign, term, content FROM utm_templates WHERE name = $1;
@@ -0,0 +1,2 @@
+INSERT INTO utm_templates (name, source, medium, campaign, term, content)
+VALUES ($1, $2, $3, $4, $5, $6);
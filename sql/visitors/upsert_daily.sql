@@ -0,0 +1,4 @@
+INSERT INTO daily_unique_visitors (code, day, visitors)
+SELECT $1::VARCHAR, $2::DATE, $3::BIGINT
+WHERE EXISTS (SELECT 1 FROM urls WHERE code = $1)
+ON CONFLICT (code, day) DO UPDATE SET visitors = EXCLUDED.visitors;
@@ -1,27 +1,44 @@
 use crate::{
-    cache,
-    db::queries::{clicks, stats},
-    error::{ApiError, ApiResult},
+    cache::visitors,
+    db::queries::{clicks, stats, urls},
+    error::ApiResult,
+    keyspace::KeyspaceStats,
     state::AppState,
 };
 use axum::{
     Json,
-    extract::{Path, State},
+    extract::{Path, Query, State},
 };
-use serde::Serialize;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::instrument;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
+
+/// Number of campaigns listed in `/stats`
+const CAMPAIGN_STATS_LIMIT: i64 = 100;
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct StatsQuery {
+    /// Count clicks classified as bots, crawlers and link previews
+    #[serde(default)]
+    include_bots: bool,
+}
 
 #[derive(Serialize, Debug, ToSchema)]
 pub struct StatsResponse {
     total_urls: i64,
     total_clicks: i64,
+    /// URLs and clicks per `utm_campaign`, for the 100 campaigns with the most clicks
+    campaigns: Vec<stats::CampaignStats>,
+    /// How full the code space is and how often new codes collide
+    keyspace: Option<KeyspaceStats>,
 }
 
 #[utoipa::path(
     get,
     path = "/stats",
+    params(StatsQuery),
     responses(
         (status = 200, description = "Stats retrieved successfully", body = StatsResponse),
         (status = 500, description = "Internal server error"),
@@ -29,43 +46,53 @@ pub struct StatsResponse {
     tag = "analytics"
 )]
 #[instrument(skip(state))]
-pub async fn get_stats(State(state): State<Arc<AppState>>) -> ApiResult<Json<StatsResponse>> {
-    // Try cache first
-    if let Some((total_urls, total_clicks)) = cache::get_stats(&state.redis_pool).await {
-        let response = StatsResponse {
-            total_urls,
-            total_clicks,
-        };
-        return Ok(Json(response));
-    }
+pub async fn get_stats(
+    Query(query): Query<StatsQuery>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<Json<StatsResponse>> {
+    // Counters are maintained by triggers, so the totals and campaigns are a constant-time lookup
+    let (total_urls, human_clicks, bot_clicks) = stats::get_total_counts(&state.pg_pool).await?;
 
-    // Cache miss - DB
-    match stats::get_total_counts(&state.pg_pool).await {
-        Ok((total_urls, total_clicks)) => {
-            // Cache for 5 minutes
-            cache::set_stats(&state.redis_pool, total_urls, total_clicks, 300).await;
-            let response = StatsResponse {
-                total_urls,
-                total_clicks,
-            };
-            Ok(Json(response))
-        }
-        Err(e) => Err(ApiError::Database(e)),
-    }
+    let total_clicks = if query.include_bots {
+        human_clicks + bot_clicks
+    } else {
+        human_clicks
+    };
+
+    let campaigns =
+        stats::get_campaign_stats(&state.pg_pool, query.include_bots, CAMPAIGN_STATS_LIMIT).await?;
+    let keyspace = state.keyspace.latest();
+
+    Ok(Json(StatsResponse {
+        total_urls,
+        total_clicks,
+        campaigns,
+        keyspace,
+    }))
 }
 
 #[derive(Serialize, Debug, ToSchema)]
 pub struct CodeStatsResponse {
     code: String,
+    /// The `utm_campaign` the destination URL is tagged with
+    campaign: Option<String>,
     total_clicks: i64,
     daily_clicks: Vec<clicks::DailyClick>,
+    /// Clicks per hour over the last 24 hours
+    hourly_clicks: Vec<clicks::HourlyClick>,
+    referrers: Vec<clicks::ReferrerClicks>,
+    /// Clicks by visitor country, resolved from the GeoIP database
+    countries: Vec<clicks::CountryClicks>,
+    /// Clicks per A/B variant served
+    variants: Vec<clicks::VariantClicks>,
 }
 
 #[utoipa::path(
     get,
     path = "/{code}/stats",
     params(
-        ("code" = String, Path, description = "Short URL code")
+        ("code" = String, Path, description = "Short URL code"),
+        StatsQuery,
     ),
     responses(
         (status = 200, description = "Analytics retrieved successfully", body = CodeStatsResponse),
@@ -77,16 +104,41 @@ pub struct CodeStatsResponse {
 #[instrument(skip(state), fields(code = %code))]
 pub async fn get_code_stats(
     Path(code): Path<String>,
+    Query(query): Query<StatsQuery>,
     State(state): State<Arc<AppState>>,
 ) -> ApiResult<Json<CodeStatsResponse>> {
-    let total_clicks = clicks::get_code_total_clicks(&state.pg_pool, &code).await?;
+    let include_bots = query.include_bots;
+
+    let campaign = urls::find_campaign_by_code(&state.pg_pool, &code).await?;
+
+    let total_clicks = clicks::get_code_total_clicks(&state.pg_pool, &code, include_bots).await?;
+
+    let mut daily_clicks =
+        clicks::get_code_daily_clicks(&state.pg_pool, &code, include_bots).await?;
+
+    // Today's unique visitors haven't been persisted yet, read the live estimate instead
+    let today = Utc::now().date_naive();
+    if let Some(day) = daily_clicks.iter_mut().find(|day| day.date == today) {
+        day.unique_visitors = visitors::count_visitors(&state.redis_pool, &code, today).await;
+    }
+
+    let hourly_clicks = clicks::get_code_hourly_clicks(&state.pg_pool, &code, include_bots).await?;
+
+    let referrers = clicks::get_code_referrers(&state.pg_pool, &code, include_bots).await?;
+
+    let countries = clicks::get_code_countries(&state.pg_pool, &code, include_bots).await?;
 
-    let daily_clicks = clicks::get_code_daily_clicks(&state.pg_pool, &code).await?;
+    let variants = clicks::get_code_variants(&state.pg_pool, &code, include_bots).await?;
 
     let response = CodeStatsResponse {
         code,
+        campaign,
         total_clicks,
         daily_clicks,
+        hourly_clicks,
+        referrers,
+        countries,
+        variants,
     };
 
     Ok(Json(response))
@@ -1,19 +1,32 @@
-use crate::state::AppState;
+use crate::{keyspace::KeyspaceStats, state::AppState};
+use axum::Json;
 use axum::extract::State;
 use axum::http::StatusCode;
+use serde::Serialize;
 use std::sync::Arc;
 use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct HealthResponse {
+    postgres: bool,
+    redis: bool,
+    /// Conditions that need attention but don't make the service unhealthy
+    warnings: Vec<String>,
+    /// Code space usage from the last periodic check, missing until the first one completed
+    keyspace: Option<KeyspaceStats>,
+}
 
 #[utoipa::path(
     get,
     path = "/health",
     responses(
-        (status = 200, description = "Service is healthy"),
-        (status = 503, description = "Service is unhealthy")
+        (status = 200, description = "Service is healthy, possibly with warnings", body = HealthResponse),
+        (status = 503, description = "Service is unhealthy", body = HealthResponse)
     ),
     tag = "health"
 )]
-pub async fn health(State(state): State<Arc<AppState>>) -> StatusCode {
+pub async fn health(State(state): State<Arc<AppState>>) -> (StatusCode, Json<HealthResponse>) {
     let pg_ok = sqlx::query("SELECT 1")
         .execute(&state.pg_pool)
         .await
@@ -27,11 +40,31 @@ pub async fn health(State(state): State<Arc<AppState>>) -> StatusCode {
         Err(_) => false,
     };
 
-    if pg_ok && redis_ok {
+    let keyspace = state.keyspace.latest();
+
+    let mut warnings = Vec::new();
+    if let Some(keyspace) = keyspace.as_ref().filter(|keyspace| keyspace.saturated) {
+        warnings.push(format!(
+            "code space saturated, {:.2}% of new codes collide",
+            keyspace.collision_probability * 100.0
+        ));
+    }
+
+    let status = if pg_ok && redis_ok {
         debug!("healthy");
         StatusCode::OK
     } else {
         warn!(pg_ok, redis_ok, "unhealthy");
         StatusCode::SERVICE_UNAVAILABLE
-    }
+    };
+
+    (
+        status,
+        Json(HealthResponse {
+            postgres: pg_ok,
+            redis: redis_ok,
+            warnings,
+            keyspace,
+        }),
+    )
 }
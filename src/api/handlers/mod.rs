@@ -1,4 +1,8 @@
 pub mod analytics;
 pub mod health;
+pub mod moderation;
+pub mod preview;
+pub mod qr;
 pub mod redirect;
 pub mod shorten;
+pub mod utm;
@@ -0,0 +1,212 @@
+use super::redirect::client_ip;
+use crate::{
+    cache::remove_from_cache,
+    db::queries::{moderation, urls},
+    error::{ApiError, ApiResult},
+    links::moderation::{DisableReason, LinkStatus, ReportReason},
+    state::AppState,
+};
+use axum::{
+    Json,
+    extract::{ConnectInfo, FromRequestParts, Path, Query, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{net::SocketAddr, sync::Arc};
+use tracing::{info, instrument, warn};
+use utoipa::{IntoParams, ToSchema};
+
+const REPORT_DETAILS_LIMIT: usize = 1000;
+const REPORTS_PAGE_LIMIT: i64 = 100;
+
+#[derive(Deserialize, ToSchema)]
+pub struct ReportPayload {
+    pub reason: ReportReason,
+    /// Anything that helps a moderator review the link
+    #[schema(max_length = 1000, example = "Pretends to be my bank's login page")]
+    pub details: Option<String>,
+}
+
+/// Reports a link for review. Once enough different visitors report a link it is flagged
+/// and visitors have to confirm the destination until a moderator reviews it.
+#[utoipa::path(
+    post,
+    path = "/{code}/report",
+    params(
+        ("code" = String, Path, description = "Short URL code to report")
+    ),
+    request_body = ReportPayload,
+    responses(
+        (status = 202, description = "Report received"),
+        (status = 400, description = "Details too long"),
+        (status = 404, description = "URL not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "moderation"
+)]
+#[instrument(skip(state, peer, headers, payload), fields(code = %code))]
+pub async fn report_link(
+    Path(code): Path<String>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<ReportPayload>,
+) -> ApiResult<StatusCode> {
+    let details = payload
+        .details
+        .as_deref()
+        .map(str::trim)
+        .filter(|details| !details.is_empty());
+    if details.is_some_and(|details| details.chars().count() > REPORT_DETAILS_LIMIT) {
+        return Err(ApiError::InvalidReport {
+            reason: format!("details must be at most {REPORT_DETAILS_LIMIT} characters"),
+        });
+    }
+
+    if urls::find_created_at(&state.pg_pool, &code)
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::NotFound);
+    }
+
+    // Reporters are only kept as a salted hash, enough to count each of them once
+    let ip = client_ip(
+        &headers,
+        peer.ip(),
+        state.config.client_ip_header.as_deref(),
+    );
+    let reporter = format!(
+        "{:x}",
+        Sha256::new()
+            .chain_update(state.report_hash_secret.as_bytes())
+            .chain_update(ip.to_string().as_bytes())
+            .finalize()
+    );
+
+    moderation::insert_report(&state.pg_pool, &code, payload.reason, details, &reporter).await?;
+    info!(reason = ?payload.reason, "Link reported");
+
+    if moderation::flag_reported(&state.pg_pool, &code, state.config.report_flag_threshold).await? {
+        warn!("Link flagged for review after repeated reports");
+        remove_from_cache(&state.redis_pool, &code).await;
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Requires `Authorization: Bearer <ADMIN_TOKEN>`. Every request is rejected while no
+/// token is configured.
+pub struct Admin;
+
+impl FromRequestParts<Arc<AppState>> for Admin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(expected) = state.config.admin_token.as_deref() else {
+            warn!("Admin request rejected, ADMIN_TOKEN is not set");
+            return Err(ApiError::Unauthorized);
+        };
+
+        let provided = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+
+        // Comparing digests keeps the comparison time independent of the token
+        if Sha256::digest(provided.as_bytes()) == Sha256::digest(expected.as_bytes()) {
+            Ok(Admin)
+        } else {
+            warn!("Admin request with an invalid token");
+            Err(ApiError::Unauthorized)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct ReportsQuery {
+    /// Number of links to return, at most 100
+    #[param(default = 50, maximum = 100)]
+    limit: Option<i64>,
+    #[param(default = 0)]
+    offset: Option<i64>,
+}
+
+/// Moderation queue of links with open reports, most reported first
+#[utoipa::path(
+    get,
+    path = "/admin/reports",
+    params(ReportsQuery),
+    responses(
+        (status = 200, description = "Reported links", body = Vec<moderation::ReportedLink>),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(("admin_token" = [])),
+    tag = "moderation"
+)]
+#[instrument(skip(state, _admin))]
+pub async fn list_reports(
+    _admin: Admin,
+    Query(query): Query<ReportsQuery>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<Json<Vec<moderation::ReportedLink>>> {
+    let limit = query.limit.unwrap_or(50).clamp(1, REPORTS_PAGE_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let reported = moderation::list_reported(&state.pg_pool, limit, offset).await?;
+    Ok(Json(reported))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct StatusPayload {
+    pub status: LinkStatus,
+    /// Why a link is disabled, `legal` serves 451 instead of 410. Defaults to `abuse` and
+    /// is ignored for other statuses.
+    pub reason: Option<DisableReason>,
+}
+
+/// Sets a link's moderation status. Activating or disabling a link resolves its open
+/// reports, and activating it also clears a threat list match.
+#[utoipa::path(
+    put,
+    path = "/admin/links/{code}/status",
+    params(
+        ("code" = String, Path, description = "Short URL code to moderate")
+    ),
+    request_body = StatusPayload,
+    responses(
+        (status = 204, description = "Status updated"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 404, description = "URL not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(("admin_token" = [])),
+    tag = "moderation"
+)]
+#[instrument(skip(state, _admin), fields(code = %code))]
+pub async fn set_link_status(
+    _admin: Admin,
+    Path(code): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<StatusPayload>,
+) -> ApiResult<StatusCode> {
+    let reason =
+        (payload.status == LinkStatus::Disabled).then(|| payload.reason.unwrap_or_default());
+
+    if !moderation::set_status(&state.pg_pool, &code, payload.status, reason).await? {
+        return Err(ApiError::NotFound);
+    }
+    if payload.status != LinkStatus::Flagged {
+        moderation::resolve_reports(&state.pg_pool, &code).await?;
+    }
+    remove_from_cache(&state.redis_pool, &code).await;
+
+    info!(status = ?payload.status, reason = ?reason, "Link status updated");
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,72 @@
+use super::redirect::{check_availability, load_link};
+use crate::{
+    api::pages::{self, Preview},
+    db::queries::{clicks, urls},
+    error::{ApiError, ApiResult},
+    links::moderation::LinkStatus,
+    state::AppState,
+};
+use axum::{
+    extract::{Path, State},
+    response::Response,
+};
+use chrono::Utc;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+/// Shows where a short link goes without following it or recording a click.
+/// `/{code}+` serves the same page.
+#[utoipa::path(
+    get,
+    path = "/{code}/preview",
+    params(
+        ("code" = String, Path, description = "Short URL code to preview")
+    ),
+    responses(
+        (status = 200, description = "Preview page", content_type = "text/html"),
+        (status = 403, description = "Link disabled because its destination is on the threat list"),
+        (status = 404, description = "URL not found or not active yet"),
+        (status = 410, description = "Link has ended, a single use link was already used, or the link was taken down for abuse"),
+        (status = 451, description = "Link was taken down for legal reasons"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "urls"
+)]
+#[instrument(skip(state), fields(code = %code))]
+pub async fn preview_url(
+    Path(code): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<Response> {
+    render_preview(&state, &code).await
+}
+
+pub(super) async fn render_preview(state: &AppState, code: &str) -> ApiResult<Response> {
+    let now = Utc::now();
+    let link = load_link(state, code).await?;
+    if link.threat_detected_at.is_some() {
+        warn!("Link disabled by the threat list");
+        return Ok(pages::unsafe_warning());
+    }
+    if link.status == LinkStatus::Disabled {
+        warn!("Link disabled by a moderator");
+        return Ok(pages::link_disabled(
+            link.disabled_reason.unwrap_or_default(),
+        ));
+    }
+    check_availability(&link, now)?;
+
+    let created_at = urls::find_created_at(&state.pg_pool, code)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let clicks = clicks::get_code_total_clicks(&state.pg_pool, code, false).await?;
+
+    info!("Serving preview");
+    Ok(pages::preview(&Preview {
+        code,
+        // The destination of a protected link is only revealed after the password
+        destination: link.password_hash.is_none().then(|| link.default_url(now)),
+        varies: !link.rules.is_empty() || !link.variants.is_empty(),
+        created_at,
+        clicks,
+    }))
+}
@@ -0,0 +1,161 @@
+use super::redirect::load_link;
+use crate::{
+    cache::qr::{add_qr, get_qr},
+    error::{ApiError, ApiResult},
+    qr::{self, ErrorCorrection, HexColor, QrFormat, QrOptions},
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info, instrument};
+use utoipa::IntoParams;
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct QrQuery {
+    /// `png` or `svg`
+    #[serde(default)]
+    #[param(inline)]
+    format: QrFormat,
+    /// Width and height in pixels, 64 to 2048
+    #[param(example = 256)]
+    size: Option<u32>,
+    /// Quiet zone around the code in modules, up to 16
+    #[param(example = 4)]
+    margin: Option<u32>,
+    /// Error correction level: L, M, Q or H
+    #[serde(default)]
+    #[param(inline)]
+    ecl: ErrorCorrection,
+    /// Foreground color as six hex digits
+    #[param(example = "000000")]
+    fg: Option<String>,
+    /// Background color as six hex digits
+    #[param(example = "ffffff")]
+    bg: Option<String>,
+}
+
+impl QrQuery {
+    fn into_options(self) -> ApiResult<QrOptions> {
+        let size = self.size.unwrap_or(256);
+        if !(qr::MIN_SIZE..=qr::MAX_SIZE).contains(&size) {
+            return Err(ApiError::InvalidQrOptions {
+                reason: format!("size must be between {} and {}", qr::MIN_SIZE, qr::MAX_SIZE),
+            });
+        }
+
+        let margin = self.margin.unwrap_or(4);
+        if margin > qr::MAX_MARGIN {
+            return Err(ApiError::InvalidQrOptions {
+                reason: format!("margin must be at most {}", qr::MAX_MARGIN),
+            });
+        }
+
+        let color = |value: Option<String>, default| match value {
+            Some(value) => value
+                .parse::<HexColor>()
+                .map_err(|reason| ApiError::InvalidQrOptions { reason }),
+            None => Ok(default),
+        };
+
+        Ok(QrOptions {
+            format: self.format,
+            size,
+            margin,
+            error_correction: self.ecl,
+            foreground: color(self.fg, HexColor::BLACK)?,
+            background: color(self.bg, HexColor::WHITE)?,
+        })
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/{code}/qr",
+    params(
+        ("code" = String, Path, description = "Short URL code to encode"),
+        QrQuery,
+    ),
+    responses(
+        (status = 200, description = "QR code image", content(
+            (Vec<u8> = "image/png"),
+            (String = "image/svg+xml")
+        )),
+        (status = 400, description = "Invalid size, margin or color"),
+        (status = 404, description = "URL not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "urls"
+)]
+#[instrument(skip(state, headers), fields(code = %code))]
+pub async fn get_qr_code(
+    Path(code): Path<String>,
+    Query(query): Query<QrQuery>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    let options = query.into_options()?;
+
+    // Only existing links get a code, but inactive ones do so they can be printed ahead of time
+    load_link(&state, &code).await?;
+
+    // Without a configured public URL the code encodes the request's Host header, which
+    // clients control, so it's neither shared through Redis nor cached by proxies
+    let Some(base_url) = &state.config.public_base_url else {
+        let short_url = format!("{}/{code}", request_base_url(&headers));
+        let image = render(&short_url, &options)?;
+        return Ok(image_response(&options, "private, max-age=3600", image));
+    };
+
+    let short_url = format!("{}/{code}", base_url.trim_end_matches('/'));
+    let cache_key = format!("{}:{}", options.cache_key(), short_url);
+
+    let image = match get_qr(&state.redis_pool, &code, &cache_key).await {
+        Some(image) => {
+            info!("QR code cache hit");
+            image
+        }
+        None => {
+            let image = render(&short_url, &options)?;
+            add_qr(&state.redis_pool, &code, &cache_key, &image).await;
+            image
+        }
+    };
+
+    Ok(image_response(&options, "public, max-age=86400", image))
+}
+
+fn render(short_url: &str, options: &QrOptions) -> ApiResult<Vec<u8>> {
+    qr::render(short_url, options).map_err(|e| {
+        error!("Failed to render QR code: {}", e);
+        ApiError::QrRender(e)
+    })
+}
+
+fn image_response(options: &QrOptions, cache_control: &'static str, image: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, options.format.content_type()),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        image,
+    )
+        .into_response()
+}
+
+/// The scheme and host the request came in on
+fn request_base_url(headers: &HeaderMap) -> String {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("localhost");
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("http");
+    format!("{scheme}://{host}")
+}
@@ -1,15 +1,30 @@
+use super::preview::render_preview;
 use crate::{
-    cache::add_to_cache,
+    api::pages,
+    bots,
+    cache::{add_to_cache, get_from_cache, visitors},
     db::queries::{clicks, urls},
     error::{ApiError, ApiResult},
+    links::{
+        Link, Target, moderation::LinkStatus, password, schedule::Availability, split,
+        targeting::Visitor,
+    },
     state::AppState,
 };
 use axum::{
-    extract::{Path, State},
-    response::Redirect,
+    Form,
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
 };
-use std::sync::Arc;
 use tracing::{error, info, instrument, warn};
+use url::Url;
 
 #[utoipa::path(
     get,
@@ -18,43 +33,212 @@ use tracing::{error, info, instrument, warn};
         ("code" = String, Path, description = "Short URL code to redirect")
     ),
     responses(
-        (status = 200, description = "Redirect successful"),
-        (status = 404, description = "URL not found"),
+        (status = 301, description = "Permanent redirect to the destination URL"),
+        (status = 302, description = "Temporary redirect to the destination URL"),
+        (status = 307, description = "Temporary redirect to the destination URL"),
+        (status = 308, description = "Permanent redirect to the destination URL"),
+        (status = 200, description = "Password form for protected links, interstitial page for links that always confirm their destination, or a preview page for `/{code}+`", content_type = "text/html"),
+        (status = 403, description = "Warning page for links disabled by the threat list", content_type = "text/html"),
+        (status = 404, description = "URL not found or not active yet"),
+        (status = 410, description = "Link has ended, a single use link was already used, or the link was taken down for abuse"),
+        (status = 451, description = "Link was taken down for legal reasons", content_type = "text/html"),
         (status = 500, description = "Internal server error")
     ),
     tag = "urls"
 )]
-#[instrument(skip(state), fields(code = %code))]
+#[instrument(skip(state, headers, peer, uri), fields(code = %code))]
 pub async fn redirect_url(
     Path(code): Path<String>,
     State(state): State<Arc<AppState>>,
-) -> ApiResult<Redirect> {
-    // Try to retrieve from cache
-    if let Ok(mut conn) = state.redis_pool.get().await
-        && let Ok(Some(url)) = redis::cmd("GET")
-            .arg(format!("short:{code}"))
-            .query_async::<Option<String>>(&mut *conn)
-            .await
-    {
-        info!("Cache hit");
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    if let Some(code) = code.strip_suffix('+') {
+        return render_preview(&state, code).await;
+    }
+
+    let visit = Visit::new(&state, peer, &method, &uri, headers, None);
+    serve_redirect(&state, &code, &visit).await
+}
 
-        if let Err(e) = clicks::insert(&state.pg_pool, &code).await {
-            error!("Failed to record click analytics: {}", e);
+/// Path segments after the code are appended to the destination for links with `forward_path`
+#[utoipa::path(
+    get,
+    path = "/{code}/{path}",
+    params(
+        ("code" = String, Path, description = "Short URL code to redirect"),
+        ("path" = String, Path, description = "Path appended to the destination URL")
+    ),
+    responses(
+        (status = 301, description = "Permanent redirect to the destination URL"),
+        (status = 302, description = "Temporary redirect to the destination URL"),
+        (status = 307, description = "Temporary redirect to the destination URL"),
+        (status = 308, description = "Permanent redirect to the destination URL"),
+        (status = 200, description = "Password form for protected links, or interstitial page for links that always confirm their destination", content_type = "text/html"),
+        (status = 403, description = "Warning page for links disabled by the threat list", content_type = "text/html"),
+        (status = 404, description = "URL not found or not active yet"),
+        (status = 410, description = "Link has ended, a single use link was already used, or the link was taken down for abuse"),
+        (status = 451, description = "Link was taken down for legal reasons", content_type = "text/html"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "urls"
+)]
+#[instrument(skip(state, headers, peer, uri, _path), fields(code = %code))]
+pub async fn redirect_url_with_path(
+    Path((code, _path)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    // Forward the raw suffix rather than the decoded path parameter so encoded
+    // characters such as %2F reach the destination untouched
+    let path_suffix = uri
+        .path()
+        .trim_start_matches('/')
+        .split_once('/')
+        .map(|(_, suffix)| suffix.to_string());
+
+    let visit = Visit::new(&state, peer, &method, &uri, headers, path_suffix);
+    serve_redirect(&state, &code, &visit).await
+}
+
+#[derive(Deserialize)]
+pub struct UnlockForm {
+    password: String,
+}
+
+/// Checks the password for a protected link and sends the visitor back to the link with
+/// a cookie that skips the prompt for a while
+#[utoipa::path(
+    post,
+    path = "/{code}",
+    params(
+        ("code" = String, Path, description = "Short URL code of a password protected link")
+    ),
+    request_body(content = String, content_type = "application/x-www-form-urlencoded", description = "`password=...`"),
+    responses(
+        (status = 303, description = "Password accepted, follow the link again"),
+        (status = 401, description = "Wrong password", content_type = "text/html"),
+        (status = 404, description = "URL not found or not password protected"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "urls"
+)]
+#[instrument(skip(state, uri, form), fields(code = %code))]
+pub async fn unlock_url(
+    Path(code): Path<String>,
+    State(state): State<Arc<AppState>>,
+    uri: Uri,
+    Form(form): Form<UnlockForm>,
+) -> ApiResult<Response> {
+    unlock(&state, &code, &uri, form).await
+}
+
+#[instrument(skip(state, uri, form, _path), fields(code = %code))]
+pub async fn unlock_url_with_path(
+    Path((code, _path)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    uri: Uri,
+    Form(form): Form<UnlockForm>,
+) -> ApiResult<Response> {
+    unlock(&state, &code, &uri, form).await
+}
+
+async fn unlock(state: &AppState, code: &str, uri: &Uri, form: UnlockForm) -> ApiResult<Response> {
+    let link = load_link(state, code).await?;
+    let Some(password_hash) = link.password_hash else {
+        return Err(ApiError::NotFound);
+    };
+
+    let hash = password_hash.clone();
+    let valid = tokio::task::spawn_blocking(move || password::verify(&hash, &form.password))
+        .await
+        .unwrap_or(false);
+    if !valid {
+        warn!("Wrong password for protected link");
+        return Ok(pages::password_form(true));
+    }
+
+    info!("Protected link unlocked");
+    let cookie = password::unlock_cookie(
+        &state.config.link_cookie_secret,
+        code,
+        &password_hash,
+        Utc::now(),
+    );
+    // The form posts to the link itself, so going back to the same URL keeps any path
+    // suffix and query string
+    let location = uri
+        .path_and_query()
+        .map_or_else(|| format!("/{code}"), |pq| pq.to_string());
+    Ok((
+        StatusCode::SEE_OTHER,
+        [
+            (header::LOCATION, location),
+            (header::SET_COOKIE, cookie),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+    )
+        .into_response())
+}
+
+/// Request details that decide where a visitor is sent and how their click is recorded
+struct Visit {
+    client_ip: IpAddr,
+    country: Option<String>,
+    headers: HeaderMap,
+    query: Option<String>,
+    path_suffix: Option<String>,
+    is_bot: bool,
+}
+
+impl Visit {
+    fn new(
+        state: &AppState,
+        peer: SocketAddr,
+        method: &Method,
+        uri: &Uri,
+        headers: HeaderMap,
+        path_suffix: Option<String>,
+    ) -> Self {
+        let client_ip = client_ip(
+            &headers,
+            peer.ip(),
+            state.config.client_ip_header.as_deref(),
+        );
+        Self {
+            client_ip,
+            country: state.geoip.country(client_ip),
+            is_bot: bots::is_bot(method, &headers),
+            query: uri.query().map(String::from),
+            path_suffix,
+            headers,
         }
+    }
+}
+
+async fn serve_redirect(state: &AppState, code: &str, visit: &Visit) -> ApiResult<Response> {
+    let link = load_link(state, code).await?;
+    follow_link(state, code, &link, visit).await
+}
 
-        return Ok(Redirect::temporary(&url));
+pub(super) async fn load_link(state: &AppState, code: &str) -> ApiResult<Link> {
+    // Try to retrieve from cache
+    if let Some(link) = get_from_cache(&state.redis_pool, code).await {
+        info!("Cache hit");
+        return Ok(link);
     }
 
     // Cache miss, hit postgres
-    match urls::find_url_by_code(&state.pg_pool, &code).await {
-        Ok(Some(url)) => {
+    match urls::find_link_by_code(&state.pg_pool, code).await {
+        Ok(Some(link)) => {
             info!("Cache miss, fetched from db");
-            add_to_cache(&state.redis_pool, &code, &url).await;
-            if let Err(e) = clicks::insert(&state.pg_pool, &code).await {
-                error!("Failed to record click analytics: {}", e);
-            }
-            info!("Redirecting");
-            Ok(Redirect::temporary(&url))
+            add_to_cache(&state.redis_pool, code, &link).await;
+            Ok(link)
         }
         Ok(None) => {
             warn!("URL not found for code");
@@ -69,3 +253,187 @@ pub async fn redirect_url(
         }
     }
 }
+
+async fn follow_link(
+    state: &AppState,
+    code: &str,
+    link: &Link,
+    visit: &Visit,
+) -> ApiResult<Response> {
+    if link.threat_detected_at.is_some() {
+        warn!("Link disabled by the threat list");
+        return Ok(pages::unsafe_warning());
+    }
+    if link.status == LinkStatus::Disabled {
+        warn!("Link disabled by a moderator");
+        return Ok(pages::link_disabled(
+            link.disabled_reason.unwrap_or_default(),
+        ));
+    }
+
+    let now = Utc::now();
+    check_availability(link, now)?;
+    check_path_suffix(link, visit)?;
+
+    if let Some(password_hash) = &link.password_hash
+        && !password::is_unlocked(
+            &visit.headers,
+            &state.config.link_cookie_secret,
+            code,
+            password_hash,
+            now,
+        )
+    {
+        info!("Prompting for password");
+        return Ok(pages::password_form(false));
+    }
+
+    if link.single_use {
+        // Link previews and prefetches would burn the link before the recipient opens it
+        if visit.is_bot {
+            info!("Not consuming single use link for bot");
+            return Ok(pages::single_use_notice());
+        }
+        consume(state, code).await?;
+    }
+
+    let visitor = Visitor {
+        sticky_variant: split::sticky_label(&visit.headers, code),
+        ..Visitor::new(&visit.headers, visit.country.clone())
+    };
+    let target = link.target(&visitor, now);
+    let variant = target.variant.map(|variant| variant.label.as_str());
+
+    record_click(state, code, visit, variant).await;
+
+    info!("Redirecting");
+    let mut response = redirect_response(state, link, visit, &target, now);
+    if let Some(label) = variant
+        && visitor.sticky_variant.as_deref() != Some(label)
+        && let Ok(cookie) = HeaderValue::from_str(&split::sticky_cookie(code, label))
+    {
+        response.headers_mut().insert(header::SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// Marks a single use link as followed. Only one request can win the update, so concurrent
+/// visits across replicas can't both be redirected.
+async fn consume(state: &AppState, code: &str) -> ApiResult<()> {
+    if urls::consume(&state.pg_pool, code).await? {
+        info!("Single use link consumed");
+        Ok(())
+    } else {
+        warn!("Single use link already consumed");
+        Err(ApiError::LinkConsumed)
+    }
+}
+
+pub(super) fn check_availability(link: &Link, now: DateTime<Utc>) -> ApiResult<()> {
+    if link.consumed_at.is_some() {
+        warn!("Single use link already consumed");
+        return Err(ApiError::LinkConsumed);
+    }
+
+    match link.availability(now) {
+        Availability::Active => Ok(()),
+        Availability::NotYetActive => {
+            warn!("Link is not active yet");
+            Err(ApiError::NotFound)
+        }
+        Availability::Ended => {
+            warn!("Link has ended");
+            Err(ApiError::LinkEnded)
+        }
+    }
+}
+
+/// Extra path segments only resolve for links that forward them
+fn check_path_suffix(link: &Link, visit: &Visit) -> ApiResult<()> {
+    if visit.path_suffix.is_some() && !link.forward_path {
+        warn!("Path suffix on a link without path forwarding");
+        return Err(ApiError::NotFound);
+    }
+    Ok(())
+}
+
+fn redirect_response(
+    state: &AppState,
+    link: &Link,
+    visit: &Visit,
+    target: &Target,
+    now: DateTime<Utc>,
+) -> Response {
+    let destination = link.destination(
+        target.url,
+        visit.path_suffix.as_deref(),
+        visit.query.as_deref(),
+    );
+    // Reported links wait for review behind the same confirmation page
+    if link.interstitial || link.status == LinkStatus::Flagged {
+        return pages::interstitial(&destination);
+    }
+
+    let redirect_type = link
+        .redirect_type
+        .unwrap_or(state.config.default_redirect_type);
+    let cache_control =
+        redirect_type.cache_control(link.is_per_visitor(), link.redirect_max_age(now));
+    let mut response = redirect_type.to_response(&destination, cache_control);
+    let vary = link.vary_headers();
+    if !vary.is_empty()
+        && let Ok(value) = HeaderValue::from_str(&vary.join(", "))
+    {
+        response.headers_mut().insert(header::VARY, value);
+    }
+    response
+}
+
+async fn record_click(state: &AppState, code: &str, visit: &Visit, variant: Option<&str>) {
+    let referrer_host = referrer_host(&visit.headers);
+    if let Err(e) = clicks::insert(
+        &state.pg_pool,
+        code,
+        referrer_host.as_deref(),
+        visit.country.as_deref(),
+        variant,
+        visit.is_bot,
+    )
+    .await
+    {
+        error!("Failed to record click analytics: {}", e);
+    }
+
+    // Bots are recorded for completeness but never count as visitors
+    if visit.is_bot {
+        return;
+    }
+
+    let user_agent = visit
+        .headers
+        .get(header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    visitors::record_visit(&state.redis_pool, code, visit.client_ip, user_agent).await;
+}
+
+/// Resolves the visitor's IP, preferring the configured proxy header over the peer address.
+/// Only the last entry of a list like `X-Forwarded-For` is used, as that one was appended by
+/// the trusted proxy while earlier entries come from the client.
+pub(super) fn client_ip(
+    headers: &HeaderMap,
+    peer_ip: IpAddr,
+    client_ip_header: Option<&str>,
+) -> IpAddr {
+    client_ip_header
+        .and_then(|name| headers.get(name))
+        .and_then(|h| h.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or(peer_ip)
+}
+
+fn referrer_host(headers: &HeaderMap) -> Option<String> {
+    let referrer = headers.get(header::REFERER)?.to_str().ok()?;
+    Url::parse(referrer).ok()?.host_str().map(str::to_lowercase)
+}
@@ -1,22 +1,44 @@
 use crate::{
-    cache::add_to_cache,
-    db::{is_collision, queries::urls},
+    cache::{add_to_cache, remove_from_cache},
+    canonical::{CanonicalOptions, canonicalize},
+    db::{
+        is_collision,
+        queries::{urls, utm},
+    },
+    domains::Verdict,
     error::{ApiError, ApiResult},
+    links::{
+        Link, QueryConflict, RedirectType, moderation::LinkStatus, password,
+        schedule::ScheduledDestination, split::Variant, targeting::LinkRule,
+    },
     state::AppState,
+    threats::ThreatMatch,
+    utm::{UtmParams, campaign_of},
 };
-use axum::{Json, extract::State, http::StatusCode};
-use rand::Rng;
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+};
+use chrono::{DateTime, Utc};
 use std::sync::Arc;
 use tracing::{debug, error, info, instrument, warn};
 use url::Url;
 use utoipa::ToSchema;
+use uuid::Uuid;
 
 const URL_LENGTH_LIMIT: usize = 2048;
-const BASE62: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
-const CODE_LEN: usize = 6;
 const MAX_COLLISION_RETRIES: usize = 5;
+const RULES_LIMIT: usize = 20;
+const VARIANTS_LIMIT: usize = 10;
+const SCHEDULE_LIMIT: usize = 20;
+const PASSWORD_LENGTH_LIMIT: usize = 128;
+const CREATOR_ID_HEADER: &str = "x-creator-id";
+const CREATOR_ID_LENGTH_LIMIT: usize = 64;
+/// Unique index allowing one deduplicated link per creator and destination
+const DEDUPE_CONSTRAINT: &str = "idx_urls_dedupe";
 
-#[derive(Debug, serde::Deserialize, ToSchema)]
+#[derive(serde::Deserialize, ToSchema)]
 pub struct ShortenPayload {
     /// The URL to shorten
     #[schema(
@@ -26,6 +48,93 @@ pub struct ShortenPayload {
         format = "uri"
     )]
     pub url: String,
+
+    /// HTTP status used when redirecting (301, 302, 307 or 308). Permanent redirects
+    /// are cached by browsers, so repeat visits may not be counted as clicks.
+    /// Defaults to the service wide setting.
+    #[schema(value_type = Option<u16>, example = 307)]
+    pub redirect_type: Option<RedirectType>,
+
+    /// Merge query parameters of the short URL into the destination URL
+    #[serde(default)]
+    pub forward_query: bool,
+
+    /// Append any path after the code (`/{code}/extra/path`) to the destination path. The
+    /// single segments `stats`, `qr`, `preview` and `report` are served by this service
+    /// instead of being forwarded.
+    #[serde(default)]
+    pub forward_path: bool,
+
+    /// Which value wins when a forwarded query parameter already exists on the destination
+    #[serde(default)]
+    pub query_conflict: QueryConflict,
+
+    /// UTM parameters added to the URL, overriding any set by `utm_template`
+    pub utm: Option<UtmParams>,
+
+    /// Name of a stored UTM template to apply to the URL
+    #[schema(example = "newsletter")]
+    pub utm_template: Option<String>,
+
+    /// Alternative destinations for matching visitors, checked in order before falling back
+    /// to `url`
+    #[serde(default)]
+    pub rules: Vec<LinkRule>,
+
+    /// Weighted destinations to split traffic across when no rule matches. Each visitor
+    /// is pinned to their variant with a cookie.
+    #[serde(default)]
+    pub variants: Vec<Variant>,
+
+    /// The short URL returns 404 until this time
+    #[schema(example = "2026-06-01T09:00:00Z")]
+    pub not_before: Option<DateTime<Utc>>,
+
+    /// The short URL returns 410 Gone from this time on
+    #[schema(example = "2026-07-01T00:00:00Z")]
+    pub not_after: Option<DateTime<Utc>>,
+
+    /// Destinations that replace `url` from their start time on, in chronological order
+    #[serde(default)]
+    pub schedule: Vec<ScheduledDestination>,
+
+    /// Visitors must enter this password before being redirected
+    #[schema(format = Password)]
+    pub password: Option<String>,
+
+    /// The link works for exactly one visit and returns 410 Gone afterwards
+    #[serde(default)]
+    pub single_use: bool,
+
+    /// Always show visitors a page with the destination and let them choose to continue,
+    /// for destinations that may not be trustworthy
+    #[serde(default)]
+    pub interstitial: bool,
+
+    /// Return the creator's existing code if they already shortened this URL, defaults to
+    /// true. Set to false to always create a new link, e.g. to track clicks separately.
+    /// Links with any of the options above are never deduplicated against existing links.
+    #[schema(default = true)]
+    pub dedupe: Option<bool>,
+}
+
+impl ShortenPayload {
+    /// Whether any option beyond the destination and its UTM parameters is set. An existing
+    /// link for the same URL would behave differently, so such links are always created anew.
+    fn has_link_options(&self) -> bool {
+        self.redirect_type.is_some()
+            || self.forward_query
+            || self.forward_path
+            || self.query_conflict != QueryConflict::default()
+            || !self.rules.is_empty()
+            || !self.variants.is_empty()
+            || self.not_before.is_some()
+            || self.not_after.is_some()
+            || !self.schedule.is_empty()
+            || self.password.is_some()
+            || self.single_use
+            || self.interstitial
+    }
 }
 
 #[derive(Debug, serde::Serialize, ToSchema)]
@@ -38,17 +147,22 @@ pub struct ShortenResponse {
     post,
     path = "/shorten",
     request_body = ShortenPayload,
+    params(
+        ("X-Creator-Id" = Option<String>, Header, description = "Namespace the link belongs to. Duplicate detection only returns links of the same creator.")
+    ),
     responses(
-        (status = 200, description = "URL already exists", body = ShortenResponse),
+        (status = 200, description = "The creator already shortened this URL without link options, its existing code is returned", body = ShortenResponse),
         (status = 201, description = "URL shortened successfully", body = ShortenResponse),
-        (status = 400, description = "Invalid URL or URL too long"),
+        (status = 400, description = "Invalid URL, URL too long, invalid rule, variant, schedule, password or creator id, or unknown UTM template"),
+        (status = 403, description = "Destination domain is blocked, not on the allowlist, on the threat list, or points back at this service"),
         (status = 500, description = "Internal server error")
     ),
     tag = "urls"
 )]
-#[instrument(skip(state))]
+#[instrument(skip(state, headers, payload), fields(url = %payload.url))]
 pub async fn shorten_url(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(payload): Json<ShortenPayload>,
 ) -> ApiResult<(StatusCode, Json<ShortenResponse>)> {
     if payload.url.len() > URL_LENGTH_LIMIT {
@@ -58,32 +172,137 @@ pub async fn shorten_url(
         });
     }
 
+    let creator_id = creator_id(&headers)?;
     validate_url_format(&payload.url)?;
+    validate_rules(&payload.rules)?;
+    validate_variants(&payload.variants)?;
+    validate_schedule(&payload)?;
+    let needs_review = check_destinations(&state, &headers, &payload)?;
 
-    // Check if this URL has already been shortened (duplicate detection)
-    let existing = urls::find_code_by_url(&state.pg_pool, &payload.url).await?;
-
-    if let Some(code) = existing {
-        info!(
-            "URL already exists, returning from existing code: {}",
-            &code
+    let url = apply_utm(&state, &payload).await?;
+    if url.len() > URL_LENGTH_LIMIT {
+        warn!(
+            "URL with UTM parameters exceeds limit of {} characters",
+            URL_LENGTH_LIMIT
         );
-        add_to_cache(&state.redis_pool, &code, &payload.url).await;
-        return Ok((StatusCode::OK, Json(ShortenResponse { code })));
+        return Err(ApiError::UrlTooLong {
+            max: URL_LENGTH_LIMIT,
+        });
+    }
+    let utm_campaign = campaign_of(&url);
+    let canonical_url = canonicalize(
+        &url,
+        CanonicalOptions {
+            strip_tracking_params: state.config.strip_tracking_params,
+            fragment_policy: state.config.url_fragment_policy,
+        },
+    )?;
+
+    let password_hash = match payload.password.clone() {
+        Some(password) => Some(hash_password(password).await?),
+        None => None,
+    };
+
+    // A link with its own options must never be handed out for a request without them
+    let dedupe = payload.dedupe.unwrap_or(true) && !payload.has_link_options();
+
+    // Check if this creator already shortened this URL (duplicate detection)
+    if dedupe
+        && let Some(response) = existing_link(
+            &state,
+            &url,
+            &canonical_url,
+            creator_id.as_deref(),
+            needs_review,
+        )
+        .await?
+    {
+        return Ok(response);
     }
 
-    for _ in 0..MAX_COLLISION_RETRIES {
-        let code = generate_random_base62_code(CODE_LEN);
+    let link = Link {
+        url,
+        redirect_type: payload.redirect_type,
+        forward_query: payload.forward_query,
+        forward_path: payload.forward_path,
+        query_conflict: payload.query_conflict,
+        rules: payload.rules,
+        variants: payload.variants,
+        not_before: payload.not_before,
+        not_after: payload.not_after,
+        schedule: payload.schedule,
+        password_hash,
+        single_use: payload.single_use,
+        consumed_at: None,
+        interstitial: payload.interstitial,
+        threat_detected_at: None,
+        status: LinkStatus::Active,
+        disabled_reason: None,
+    };
+
+    // Hashed codes of links that can share a code follow from their destination, others
+    // get a fresh seed so repeated links don't walk through the same collisions
+    let seed = if dedupe {
+        format!(
+            "{}\n{}",
+            creator_id.as_deref().unwrap_or_default(),
+            canonical_url
+        )
+    } else {
+        Uuid::new_v4().to_string()
+    };
+
+    for attempt in 0..MAX_COLLISION_RETRIES {
+        // Reserved codes are only taken by a concurrent on demand code in rare races
+        let pooled = state.code_pool.pop(&state.redis_pool).await;
+        let code = match &pooled {
+            Some(code) => code.clone(),
+            None => state.codes.generate(&state.pg_pool, &seed, attempt).await?,
+        };
         debug!("Code generated: {}", &code);
 
-        match urls::insert(&state.pg_pool, &code, &payload.url).await {
+        match urls::insert(
+            &state.pg_pool,
+            &code,
+            &link,
+            &canonical_url,
+            creator_id.as_deref(),
+            dedupe,
+            utm_campaign.as_deref(),
+        )
+        .await
+        {
             Ok(_) => {
+                state.codes.record_success(&code);
                 info!("Short URL created with code: {}", &code);
-                add_to_cache(&state.redis_pool, &code, &payload.url).await;
+                if needs_review {
+                    warn!("Flagged link with a destination matching a threat list prefix");
+                    urls::flag_threat_reviews(&state.pg_pool, std::slice::from_ref(&code)).await?;
+                } else {
+                    add_to_cache(&state.redis_pool, &code, &link).await;
+                }
                 return Ok((StatusCode::CREATED, Json(ShortenResponse { code })));
             }
+            // A concurrent request created the same link first
+            Err(sqlx::Error::Database(db_err))
+                if db_err.constraint() == Some(DEDUPE_CONSTRAINT)
+                    && let Some(response) = existing_link(
+                        &state,
+                        &link.url,
+                        &canonical_url,
+                        creator_id.as_deref(),
+                        needs_review,
+                    )
+                    .await? =>
+            {
+                return Ok(response);
+            }
             Err(sqlx::Error::Database(db_err)) if is_collision(db_err.as_ref()) => {
                 warn!("Collision - retrying with new code");
+                state
+                    .codes
+                    .record_collision(&state.pg_pool, &code, pooled.is_some())
+                    .await;
                 continue;
             }
             Err(e) => {
@@ -96,6 +315,289 @@ pub async fn shorten_url(
     Err(ApiError::TooManyCollisions)
 }
 
+/// The creator's existing link for the destination, if any. `needs_review` queues it for
+/// review like a new link whose destination only matched a threat list prefix.
+async fn existing_link(
+    state: &AppState,
+    url: &str,
+    canonical_url: &str,
+    creator_id: Option<&str>,
+    needs_review: bool,
+) -> ApiResult<Option<(StatusCode, Json<ShortenResponse>)>> {
+    let Some((code, status)) =
+        urls::find_code_by_url(&state.pg_pool, canonical_url, creator_id).await?
+    else {
+        return Ok(None);
+    };
+
+    // A taken down URL can't come back under a new code
+    if status == LinkStatus::Disabled {
+        warn!("URL was already shortened and disabled as {}", &code);
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        return Err(ApiError::BlockedDestination { host });
+    }
+
+    if needs_review {
+        for code in urls::flag_threat_reviews(&state.pg_pool, std::slice::from_ref(&code)).await? {
+            warn!(%code, "Flagged link with a destination matching a threat list prefix");
+            remove_from_cache(&state.redis_pool, &code).await;
+        }
+    }
+
+    info!(
+        "URL already exists, returning from existing code: {}",
+        &code
+    );
+    Ok(Some((StatusCode::OK, Json(ShortenResponse { code }))))
+}
+
+/// The optional `X-Creator-Id` namespace. It only scopes duplicate detection and is not
+/// an authentication mechanism.
+fn creator_id(headers: &HeaderMap) -> ApiResult<Option<String>> {
+    let Some(value) = headers.get(CREATOR_ID_HEADER) else {
+        return Ok(None);
+    };
+
+    let creator_id = value
+        .to_str()
+        .map(str::trim)
+        .map_err(|_| ApiError::InvalidCreatorId)?;
+    let valid = (1..=CREATOR_ID_LENGTH_LIMIT).contains(&creator_id.len())
+        && creator_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    if !valid {
+        warn!("Rejected creator id");
+        return Err(ApiError::InvalidCreatorId);
+    }
+
+    Ok(Some(creator_id.to_string()))
+}
+
+/// Merges the requested UTM template and explicit UTM fields into the URL
+async fn apply_utm(state: &AppState, payload: &ShortenPayload) -> ApiResult<String> {
+    let template = match payload.utm_template.as_deref() {
+        Some(name) => match utm::find_template(&state.pg_pool, name).await? {
+            Some(template) => template.params,
+            None => {
+                warn!("Unknown UTM template: {}", name);
+                return Err(ApiError::UtmTemplateNotFound {
+                    name: name.to_string(),
+                });
+            }
+        },
+        None => UtmParams::default(),
+    };
+
+    let params = payload.utm.clone().unwrap_or_default().or(template);
+    Ok(params.apply(&payload.url)?)
+}
+
+fn validate_rules(rules: &[LinkRule]) -> ApiResult<()> {
+    if rules.len() > RULES_LIMIT {
+        warn!("Too many targeting rules: {}", rules.len());
+        return Err(ApiError::InvalidRule {
+            reason: format!("at most {RULES_LIMIT} rules are allowed"),
+        });
+    }
+
+    for rule in rules {
+        if !rule.is_valid() {
+            warn!("Unknown targeting rule value: {}", rule.value);
+            return Err(ApiError::InvalidRule {
+                reason: format!("unknown value {:?}", rule.value),
+            });
+        }
+        if rule.url.len() > URL_LENGTH_LIMIT {
+            return Err(ApiError::UrlTooLong {
+                max: URL_LENGTH_LIMIT,
+            });
+        }
+        validate_url_format(&rule.url)?;
+    }
+
+    Ok(())
+}
+
+fn validate_variants(variants: &[Variant]) -> ApiResult<()> {
+    if variants.len() > VARIANTS_LIMIT {
+        warn!("Too many A/B variants: {}", variants.len());
+        return Err(ApiError::InvalidVariant {
+            reason: format!("at most {VARIANTS_LIMIT} variants are allowed"),
+        });
+    }
+
+    for (i, variant) in variants.iter().enumerate() {
+        if !variant.has_valid_label() {
+            warn!("Invalid A/B variant label: {}", variant.label);
+            return Err(ApiError::InvalidVariant {
+                reason: "labels must be 1-32 letters, digits, '-' or '_'".to_string(),
+            });
+        }
+        if variants[..i]
+            .iter()
+            .any(|other| other.label == variant.label)
+        {
+            return Err(ApiError::InvalidVariant {
+                reason: format!("duplicate label {:?}", variant.label),
+            });
+        }
+        if variant.weight <= 0 {
+            return Err(ApiError::InvalidVariant {
+                reason: "weights must be positive".to_string(),
+            });
+        }
+        if variant.url.len() > URL_LENGTH_LIMIT {
+            return Err(ApiError::UrlTooLong {
+                max: URL_LENGTH_LIMIT,
+            });
+        }
+        validate_url_format(&variant.url)?;
+    }
+
+    Ok(())
+}
+
+fn validate_schedule(payload: &ShortenPayload) -> ApiResult<()> {
+    if let (Some(not_before), Some(not_after)) = (payload.not_before, payload.not_after)
+        && not_before >= not_after
+    {
+        return Err(ApiError::InvalidSchedule {
+            reason: "not_before must be earlier than not_after".to_string(),
+        });
+    }
+
+    if payload.schedule.len() > SCHEDULE_LIMIT {
+        warn!(
+            "Too many scheduled destinations: {}",
+            payload.schedule.len()
+        );
+        return Err(ApiError::InvalidSchedule {
+            reason: format!("at most {SCHEDULE_LIMIT} scheduled destinations are allowed"),
+        });
+    }
+
+    if payload
+        .schedule
+        .windows(2)
+        .any(|pair| pair[0].starts_at >= pair[1].starts_at)
+    {
+        return Err(ApiError::InvalidSchedule {
+            reason: "scheduled destinations must be in chronological order".to_string(),
+        });
+    }
+
+    for entry in &payload.schedule {
+        if entry.url.len() > URL_LENGTH_LIMIT {
+            return Err(ApiError::UrlTooLong {
+                max: URL_LENGTH_LIMIT,
+            });
+        }
+        validate_url_format(&entry.url)?;
+    }
+
+    Ok(())
+}
+
+/// Rejects destinations on the domain blocklist or threat list, missing from the allowlist,
+/// or pointing back at this service, which would redirect in a loop. Returns whether a
+/// destination matched only a threat list hash prefix, which is left to a moderator.
+fn check_destinations(
+    state: &AppState,
+    headers: &HeaderMap,
+    payload: &ShortenPayload,
+) -> ApiResult<bool> {
+    let own_hosts = own_hosts(state, headers);
+    let mut needs_review = false;
+    let destinations = std::iter::once(&payload.url)
+        .chain(payload.rules.iter().map(|rule| &rule.url))
+        .chain(payload.variants.iter().map(|variant| &variant.url))
+        .chain(payload.schedule.iter().map(|entry| &entry.url));
+
+    for destination in destinations {
+        let Some(host) = Url::parse(destination).ok().and_then(|url| {
+            url.host_str()
+                .map(|host| host.trim_end_matches('.').to_string())
+        }) else {
+            continue;
+        };
+
+        let blocked = if own_hosts.contains(&host) {
+            warn!(%host, "Rejected destination pointing back at this service");
+            true
+        } else {
+            match state.domains.check(&host) {
+                Verdict::Allowed => false,
+                Verdict::Blocked => {
+                    warn!(%host, "Rejected destination on the domain blocklist");
+                    true
+                }
+                Verdict::NotAllowed => {
+                    warn!(%host, "Rejected destination missing from the domain allowlist");
+                    true
+                }
+            }
+        };
+        if blocked {
+            return Err(ApiError::BlockedDestination { host });
+        }
+
+        match state.threats.check(destination) {
+            Some(ThreatMatch::Full) => {
+                warn!(%host, "Rejected destination on the threat list");
+                return Err(ApiError::UnsafeDestination { host });
+            }
+            Some(ThreatMatch::Prefix) => needs_review = true,
+            None => {}
+        }
+    }
+
+    Ok(needs_review)
+}
+
+/// Hosts this service is reachable on: the configured public URL and the request's `Host`
+fn own_hosts(state: &AppState, headers: &HeaderMap) -> Vec<String> {
+    let public = state
+        .config
+        .public_base_url
+        .as_deref()
+        .and_then(|base| Url::parse(base).ok());
+    let requested = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| Url::parse(&format!("http://{host}")).ok());
+
+    public
+        .into_iter()
+        .chain(requested)
+        .filter_map(|url| {
+            url.host_str()
+                .map(|host| host.trim_end_matches('.').to_string())
+        })
+        .collect()
+}
+
+async fn hash_password(password: String) -> ApiResult<String> {
+    if password.is_empty() || password.chars().count() > PASSWORD_LENGTH_LIMIT {
+        warn!("Rejected link password");
+        return Err(ApiError::InvalidPassword {
+            max: PASSWORD_LENGTH_LIMIT,
+        });
+    }
+
+    tokio::task::spawn_blocking(move || password::hash(&password))
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .ok_or_else(|| {
+            error!("Failed to hash link password");
+            ApiError::PasswordHashFailed
+        })
+}
+
 fn validate_url_format(url: &str) -> ApiResult<()> {
     let parsed = Url::parse(url).map_err(|e| {
         warn!("Invalid URL format: {}", e);
@@ -114,13 +616,3 @@ fn validate_url_format(url: &str) -> ApiResult<()> {
         })
     }
 }
-
-fn generate_random_base62_code(length: usize) -> String {
-    let mut rng = rand::rng();
-    (0..length)
-        .map(|_| {
-            let idx = rng.random_range(0..62);
-            BASE62[idx] as char
-        })
-        .collect()
-}
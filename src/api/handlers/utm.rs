@@ -0,0 +1,86 @@
+use super::moderation::Admin;
+use crate::{
+    db::{is_collision, queries::utm},
+    error::{ApiError, ApiResult},
+    state::AppState,
+    utm::UtmTemplate,
+};
+use axum::{Json, extract::State, http::StatusCode};
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+const TEMPLATE_NAME_LIMIT: usize = 64;
+const TEMPLATE_VALUE_LIMIT: usize = 256;
+
+#[utoipa::path(
+    post,
+    path = "/utm-templates",
+    request_body = UtmTemplate,
+    responses(
+        (status = 201, description = "Template created", body = UtmTemplate),
+        (status = 400, description = "Invalid template name or parameter value"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 409, description = "A template with this name already exists"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(("admin_token" = [])),
+    tag = "utm"
+)]
+#[instrument(skip(state, _admin))]
+pub async fn create_template(
+    _admin: Admin,
+    State(state): State<Arc<AppState>>,
+    Json(template): Json<UtmTemplate>,
+) -> ApiResult<(StatusCode, Json<UtmTemplate>)> {
+    let name = template.name.trim();
+    if name.is_empty() || name.chars().count() > TEMPLATE_NAME_LIMIT {
+        warn!("Rejected UTM template name");
+        return Err(ApiError::InvalidUtmTemplate {
+            reason: format!("name must be 1-{TEMPLATE_NAME_LIMIT} characters"),
+        });
+    }
+    if let Some((key, _)) = template
+        .params
+        .pairs()
+        .find(|(_, value)| value.chars().count() > TEMPLATE_VALUE_LIMIT)
+    {
+        warn!("Rejected UTM template value");
+        return Err(ApiError::InvalidUtmTemplate {
+            reason: format!("{key} must be at most {TEMPLATE_VALUE_LIMIT} characters"),
+        });
+    }
+
+    match utm::insert_template(&state.pg_pool, name, &template.params).await {
+        Ok(_) => {
+            info!("UTM template created: {}", name);
+            let template = UtmTemplate {
+                name: name.to_string(),
+                params: template.params,
+            };
+            Ok((StatusCode::CREATED, Json(template)))
+        }
+        Err(sqlx::Error::Database(db_err)) if is_collision(db_err.as_ref()) => {
+            Err(ApiError::UtmTemplateExists {
+                name: name.to_string(),
+            })
+        }
+        Err(e) => Err(ApiError::Database(e)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/utm-templates",
+    responses(
+        (status = 200, description = "Templates retrieved successfully", body = Vec<UtmTemplate>),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = "utm"
+)]
+#[instrument(skip(state))]
+pub async fn list_templates(
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<Json<Vec<UtmTemplate>>> {
+    let templates = utm::list_templates(&state.pg_pool).await?;
+    Ok(Json(templates))
+}
@@ -2,40 +2,77 @@ use crate::config::RateLimitConfig;
 use crate::state::AppState;
 use axum::{
     Router,
-    routing::{get, post},
+    routing::{get, post, put},
 };
 use std::sync::Arc;
 use tower::ServiceBuilder;
 use tower_http::{cors::CorsLayer, services::ServeFile};
-use utoipa::OpenApi;
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
 use utoipa_swagger_ui::SwaggerUi;
 
 mod handlers;
 mod middleware;
+mod pages;
 
 #[derive(OpenApi)]
 #[openapi(
       paths(
           handlers::shorten::shorten_url,
           handlers::redirect::redirect_url,
+          handlers::redirect::redirect_url_with_path,
+          handlers::redirect::unlock_url,
+          handlers::preview::preview_url,
+          handlers::qr::get_qr_code,
+          handlers::moderation::report_link,
+          handlers::moderation::list_reports,
+          handlers::moderation::set_link_status,
           handlers::analytics::get_stats,
           handlers::analytics::get_code_stats,
           handlers::health::health,
+          handlers::utm::create_template,
+          handlers::utm::list_templates,
       ),
       components(
           schemas(
               handlers::shorten::ShortenPayload,
               handlers::shorten::ShortenResponse,
+              crate::links::QueryConflict,
+              crate::links::targeting::LinkRule,
+              crate::links::targeting::RuleKind,
+              crate::links::split::Variant,
+              crate::links::schedule::ScheduledDestination,
+              crate::links::moderation::LinkStatus,
+              crate::links::moderation::DisableReason,
+              crate::links::moderation::ReportReason,
+              handlers::moderation::ReportPayload,
+              handlers::moderation::StatusPayload,
+              crate::db::queries::moderation::ReportedLink,
               handlers::analytics::StatsResponse,
               handlers::analytics::CodeStatsResponse,
               crate::db::queries::clicks::DailyClick,
+              crate::db::queries::clicks::HourlyClick,
+              crate::db::queries::clicks::ReferrerClicks,
+              crate::db::queries::clicks::CountryClicks,
+              crate::db::queries::clicks::VariantClicks,
+              crate::db::queries::stats::CampaignStats,
+              crate::keyspace::KeyspaceStats,
+              crate::keyspace::CodeLengthStats,
+              handlers::health::HealthResponse,
+              crate::utm::UtmParams,
+              crate::utm::UtmTemplate,
           )
       ),
       tags(
           (name = "urls", description = "URL shortening and redirect operations"),
           (name = "analytics", description = "URL shortening and redirect analytics"),
+          (name = "utm", description = "Stored UTM parameter templates"),
+          (name = "moderation", description = "Abuse reports and link takedowns"),
           (name = "health", description = "Health check endpoints")
       ),
+      modifiers(&AdminTokenAddon),
       info(
           title = "Turbo Guacamole URL Shortener",
           version = "0.1.0",
@@ -44,6 +81,20 @@ mod middleware;
   )]
 pub struct ApiDoc;
 
+/// Documents the `ADMIN_TOKEN` bearer auth of the moderation endpoints
+struct AdminTokenAddon;
+
+impl Modify for AdminTokenAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "admin_token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
+
 pub fn configure(
     redirect_rate_limit_config: &RateLimitConfig,
     shorten_rate_limit_config: &RateLimitConfig,
@@ -59,20 +110,54 @@ pub fn configure(
         .route_service("/", ServeFile::new("static/index.html"))
         .route(
             "/shorten",
-            post(handlers::shorten::shorten_url).layer(shorten_rate_limit),
+            post(handlers::shorten::shorten_url).layer(shorten_rate_limit.clone()),
         )
         .route(
             "/stats",
             get(handlers::analytics::get_stats).layer(default_rate_limit.clone()),
         )
         .route("/health", get(handlers::health::health))
+        .route(
+            "/utm-templates",
+            get(handlers::utm::list_templates)
+                .post(handlers::utm::create_template)
+                .layer(default_rate_limit.clone()),
+        )
+        .route(
+            "/admin/reports",
+            get(handlers::moderation::list_reports).layer(default_rate_limit.clone()),
+        )
+        .route(
+            "/admin/links/{code}/status",
+            put(handlers::moderation::set_link_status).layer(default_rate_limit.clone()),
+        )
+        .route(
+            "/{code}/report",
+            post(handlers::moderation::report_link).layer(shorten_rate_limit),
+        )
         .route(
             "/{code}/stats",
-            get(handlers::analytics::get_code_stats).layer(default_rate_limit),
+            get(handlers::analytics::get_code_stats).layer(default_rate_limit.clone()),
+        )
+        .route(
+            "/{code}/qr",
+            get(handlers::qr::get_qr_code).layer(default_rate_limit),
+        )
+        .route(
+            "/{code}/preview",
+            get(handlers::preview::preview_url).layer(redirect_rate_limit.clone()),
         )
         .route(
             "/{code}",
-            get(handlers::redirect::redirect_url).layer(redirect_rate_limit),
+            get(handlers::redirect::redirect_url)
+                .post(handlers::redirect::unlock_url)
+                .layer(redirect_rate_limit.clone()),
+        )
+        .route(
+            "/{code}/{*path}",
+            get(handlers::redirect::redirect_url_with_path)
+                .post(handlers::redirect::unlock_url_with_path)
+                .layer(redirect_rate_limit),
         )
         .layer(
             ServiceBuilder::new()
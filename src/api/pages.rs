@@ -0,0 +1,166 @@
+//! Minimal server rendered HTML pages for visitors following a short link
+
+use crate::links::moderation::DisableReason;
+use axum::{
+    http::{HeaderValue, StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::NaiveDateTime;
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Wraps `body` in a bare page. `title` is escaped here, `body` must already be safe HTML.
+fn layout(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>{title}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; max-width: 32rem; margin: 4rem auto; padding: 0 1rem; color: #222; }}
+input, button {{ font: inherit; padding: 0.4rem 0.6rem; }}
+.error {{ color: #b00020; }}
+</style>
+</head>
+<body>
+{body}
+</body>
+</html>
+"#,
+        title = escape(title),
+    )
+}
+
+/// Pages depend on the visitor and the link's current state, so they're never cached
+fn respond(status: StatusCode, html: String) -> Response {
+    let mut response = (status, Html(html)).into_response();
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+/// Prompt for a protected link. The form posts back to the URL it was served from.
+pub fn password_form(wrong_password: bool) -> Response {
+    let (status, error) = if wrong_password {
+        (
+            StatusCode::UNAUTHORIZED,
+            r#"<p class="error">Wrong password, please try again.</p>"#,
+        )
+    } else {
+        (StatusCode::OK, "")
+    };
+
+    let body = format!(
+        r#"<h1>This link is password protected</h1>
+{error}
+<form method="post">
+<input type="password" name="password" placeholder="Password" autofocus required>
+<button type="submit">Continue</button>
+</form>"#
+    );
+    respond(status, layout("Password required", &body))
+}
+
+/// Shown to bots and link previews instead of following a single use link
+pub fn single_use_notice() -> Response {
+    let body = r#"<h1>This link can only be opened once</h1>
+<p>Open it in your browser to continue.</p>"#;
+    respond(StatusCode::OK, layout("One-time link", body))
+}
+
+pub struct Preview<'a> {
+    pub code: &'a str,
+    /// `None` hides the destination, e.g. for password protected links
+    pub destination: Option<&'a str>,
+    /// Targeting rules or A/B variants may send visitors somewhere else
+    pub varies: bool,
+    pub created_at: NaiveDateTime,
+    pub clicks: i64,
+}
+
+/// Describes where a short link goes without following it
+pub fn preview(preview: &Preview) -> Response {
+    let destination = match preview.destination {
+        Some(url) => format!(
+            r#"<p>This link goes to</p>
+<p><a href="{url}" rel="noopener noreferrer nofollow"><code>{url}</code></a></p>"#,
+            url = escape(url)
+        ),
+        None => "<p>This link is password protected, its destination is hidden.</p>".to_string(),
+    };
+    let varies = if preview.varies {
+        "<p>Some visitors are sent to a different destination based on their device, location, language or an A/B split.</p>"
+    } else {
+        ""
+    };
+
+    let body = format!(
+        r#"<h1>Link preview</h1>
+{destination}
+{varies}
+<p>Created {created_at} &middot; {clicks} clicks</p>
+<p><a href="/{code}">Follow the link</a></p>"#,
+        created_at = preview.created_at.format("%Y-%m-%d %H:%M"),
+        clicks = preview.clicks,
+        code = escape(preview.code),
+    );
+    respond(StatusCode::OK, layout("Link preview", &body))
+}
+
+/// Shown instead of redirecting for links that always ask visitors to confirm the destination
+pub fn interstitial(destination: &str) -> Response {
+    let body = format!(
+        r#"<h1>You are leaving for another site</h1>
+<p>This link goes to</p>
+<p><code>{url}</code></p>
+<p>Only continue if you trust this destination.</p>
+<p><a href="{url}" rel="noopener noreferrer nofollow">Continue</a></p>"#,
+        url = escape(destination)
+    );
+    respond(StatusCode::OK, layout("Leaving for another site", &body))
+}
+
+/// Served instead of redirecting once a link's destination matched the threat list. The
+/// destination is deliberately not linked.
+pub fn unsafe_warning() -> Response {
+    let body = r#"<h1>This link has been disabled</h1>
+<p class="error">Its destination was flagged as phishing, malware or otherwise unsafe.</p>
+<p>If you were expecting this link to work, contact whoever shared it with you.</p>"#;
+    respond(StatusCode::FORBIDDEN, layout("Unsafe link disabled", body))
+}
+
+/// Served for links taken down by a moderator
+pub fn link_disabled(reason: DisableReason) -> Response {
+    let (status, title, body) = match reason {
+        DisableReason::Abuse => (
+            StatusCode::GONE,
+            "Link removed",
+            r#"<h1>This link has been removed</h1>
+<p>It was taken down for violating our terms of use.</p>"#,
+        ),
+        DisableReason::Legal => (
+            StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+            "Link unavailable",
+            r#"<h1>This link is unavailable</h1>
+<p>It was taken down in response to a legal request.</p>"#,
+        ),
+    };
+    respond(status, layout(title, body))
+}
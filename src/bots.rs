@@ -0,0 +1,54 @@
+use axum::http::{HeaderMap, Method, header};
+use std::sync::LazyLock;
+
+/// Lowercased user agent substrings that identify crawlers, link unfurlers and scripted clients
+static BOT_PATTERNS: LazyLock<Vec<&'static str>> = LazyLock::new(|| {
+    include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/data/bot_user_agents.txt"
+    ))
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    .collect()
+});
+
+/// Prefetch and preview hints sent by browsers and apps that aren't a real visit
+const PREFETCH_HEADERS: [(&str, &str); 4] = [
+    ("purpose", "prefetch"),
+    ("sec-purpose", "prefetch"),
+    ("x-purpose", "preview"),
+    ("x-moz", "prefetch"),
+];
+
+/// Classifies a request as automated traffic that shouldn't count as a click
+pub fn is_bot(method: &Method, headers: &HeaderMap) -> bool {
+    // Unfurlers commonly probe links with HEAD before (or instead of) fetching them
+    if method == Method::HEAD {
+        return true;
+    }
+
+    let is_prefetch = PREFETCH_HEADERS.iter().any(|(name, hint)| {
+        headers
+            .get(*name)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|value| value.to_ascii_lowercase().contains(hint))
+    });
+    if is_prefetch {
+        return true;
+    }
+
+    match headers
+        .get(header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+    {
+        Some(user_agent) if !user_agent.trim().is_empty() => {
+            let user_agent = user_agent.to_ascii_lowercase();
+            BOT_PATTERNS
+                .iter()
+                .any(|pattern| user_agent.contains(pattern))
+        }
+        // Real browsers always send a user agent
+        _ => true,
+    }
+}
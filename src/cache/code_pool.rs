@@ -0,0 +1,211 @@
+use super::RedisPool;
+use crate::{
+    codes::{CodeGenerator, CodeStrategy},
+    db::queries::urls,
+};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Notify;
+use tracing::{debug, error, info};
+
+/// How often the pool is checked when nobody asks for a refill
+const REFILL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Codes generated and checked against Postgres in one go
+const REFILL_BATCH: usize = 500;
+
+/// Pools of an earlier code configuration expire once no replica tops them up anymore
+const POOL_TTL_SECS: u64 = 86400;
+
+/// Codes generated ahead of time and reserved in a Redis set, so creating a link usually
+/// doesn't have to generate codes or retry collisions. All replicas share the set, which
+/// holds every code at most once, and a code popped from it is only handed out once.
+#[derive(Clone)]
+pub struct CodePool {
+    /// Number of codes kept in the pool, 0 disables it
+    size: usize,
+    codes: CodeGenerator,
+    refill: Arc<Notify>,
+}
+
+impl CodePool {
+    /// Pool for codes of the generator's configuration. Hashed codes depend on their
+    /// destination and are never pooled.
+    pub fn new(size: usize, codes: &CodeGenerator) -> Self {
+        let size = if codes.strategy() == CodeStrategy::Hash {
+            if size > 0 {
+                info!("Code pool disabled, hashed codes can't be generated ahead of time");
+            }
+            0
+        } else {
+            size
+        };
+
+        Self {
+            size,
+            codes: codes.clone(),
+            refill: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Set holding codes of the current strategy, secret, alphabet and length. Changing any
+    /// of them, including growing codes after collisions, moves on to a new set instead of
+    /// handing out codes of the old configuration.
+    fn key(&self) -> String {
+        let fingerprint = Sha256::new()
+            .chain_update(self.codes.strategy().to_string().as_bytes())
+            .chain_update(self.codes.key())
+            .chain_update(self.codes.alphabet().to_string().as_bytes())
+            .chain_update(self.codes.length().to_be_bytes())
+            .finalize();
+        format!("code_pool:{}", hex::encode(&fingerprint[..8]))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.size > 0
+    }
+
+    /// Takes a reserved code, or `None` if the pool is disabled, empty or Redis is
+    /// unavailable. Asks the refill task to top the pool up once it runs low.
+    pub async fn pop(&self, pool: &RedisPool) -> Option<String> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let Ok(mut conn) = pool.get().await else {
+            debug!("Failed to connect to redis pool when taking a pooled code");
+            return None;
+        };
+
+        let key = self.key();
+        let (code, remaining) = redis::pipe()
+            .cmd("SPOP")
+            .arg(&key)
+            .cmd("SCARD")
+            .arg(&key)
+            .query_async::<(Option<String>, usize)>(&mut *conn)
+            .await
+            .ok()?;
+
+        if remaining < self.size / 2 {
+            self.refill.notify_one();
+        }
+        if code.is_none() {
+            debug!("Code pool is empty");
+        }
+        code
+    }
+
+    /// Reserves codes until the pool holds `size` of them, returning how many were added
+    async fn refill(
+        &self,
+        redis_pool: &RedisPool,
+        pg_pool: &PgPool,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let key = self.key();
+        let mut conn = redis_pool.get().await?;
+        let pooled: usize = redis::cmd("SCARD")
+            .arg(&key)
+            .query_async(&mut *conn)
+            .await?;
+
+        let mut added = 0;
+        while pooled + added < self.size {
+            let count = (self.size - pooled - added).min(REFILL_BATCH);
+            let candidates = self.codes.generate_batch(pg_pool, count).await?;
+
+            // Collisions with links created in the meantime are still caught on insert
+            let unused = urls::filter_unused_codes(pg_pool, &candidates).await?;
+            if unused.is_empty() {
+                break;
+            }
+
+            // Codes already in the set aren't added again, so only new ones are counted
+            let (new,): (usize,) = redis::pipe()
+                .cmd("SADD")
+                .arg(&key)
+                .arg(&unused)
+                .cmd("EXPIRE")
+                .arg(&key)
+                .arg(POOL_TTL_SECS)
+                .ignore()
+                .query_async(&mut *conn)
+                .await?;
+            if new == 0 {
+                break;
+            }
+            added += new;
+        }
+
+        Ok(added)
+    }
+
+    /// Keeps the pool topped up, checking periodically and whenever it runs low
+    pub fn start_refill_task(&self, redis_pool: RedisPool, pg_pool: PgPool) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let code_pool = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match code_pool.refill(&redis_pool, &pg_pool).await {
+                    Ok(0) => {}
+                    Ok(added) => info!("Added {} codes to the code pool", added),
+                    Err(e) => error!("Failed to refill the code pool: {}", e),
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(REFILL_INTERVAL) => {}
+                    _ = code_pool.refill.notified() => {}
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::setup_cache, codes::Alphabet};
+
+    /// Runs against the services named by `TEST_REDIS_URL` and `TEST_DATABASE_URL`, the
+    /// database needs `sql/schema.sql` applied
+    #[tokio::test]
+    #[ignore = "needs Redis and Postgres"]
+    async fn refill_tops_up_an_existing_pool() {
+        let redis_pool = setup_cache(&std::env::var("TEST_REDIS_URL").unwrap())
+            .await
+            .unwrap();
+        let pg_pool = PgPool::connect(&std::env::var("TEST_DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+
+        // A secret of its own keeps the test's set apart from any other pool
+        let secret = uuid::Uuid::new_v4().to_string();
+        let codes = CodeGenerator::new(CodeStrategy::Random, Alphabet::default(), 8, Some(&secret))
+            .unwrap();
+        let code_pool = CodePool::new(20, &codes);
+
+        assert_eq!(code_pool.refill(&redis_pool, &pg_pool).await.unwrap(), 20);
+        for _ in 0..5 {
+            assert!(code_pool.pop(&redis_pool).await.is_some());
+        }
+        assert_eq!(code_pool.refill(&redis_pool, &pg_pool).await.unwrap(), 5);
+        assert_eq!(code_pool.refill(&redis_pool, &pg_pool).await.unwrap(), 0);
+
+        let mut conn = redis_pool.get().await.unwrap();
+        let pooled: usize = redis::cmd("SCARD")
+            .arg(code_pool.key())
+            .query_async(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(pooled, 20);
+        let _: () = redis::cmd("DEL")
+            .arg(code_pool.key())
+            .query_async(&mut *conn)
+            .await
+            .unwrap();
+    }
+}
@@ -1,5 +1,13 @@
+pub mod code_pool;
+pub mod qr;
+pub mod visitors;
+
+use crate::links::Link;
+use chrono::Utc;
 use tracing::debug;
 
+const LINK_TTL_SECS: u64 = 3600;
+
 pub type RedisPool = bb8::Pool<redis::Client>;
 
 pub async fn setup_cache(url: &str) -> Result<RedisPool, redis::RedisError> {
@@ -7,13 +15,26 @@ pub async fn setup_cache(url: &str) -> Result<RedisPool, redis::RedisError> {
     bb8::Pool::builder().build(client).await
 }
 
-pub async fn add_to_cache(pool: &RedisPool, code: &str, url: &str) {
+/// Caches the link until it next changes (activation, expiry or a scheduled destination
+/// switch), for at most an hour. Single use links are never cached so every visit has to
+/// go through the database to consume them.
+pub async fn add_to_cache(pool: &RedisPool, code: &str, link: &Link) {
+    if link.single_use {
+        return;
+    }
+
+    let ttl = link.ttl(Utc::now(), LINK_TTL_SECS).max(1);
+    let Ok(value) = serde_json::to_string(link) else {
+        debug!("Failed to serialize link for cache");
+        return;
+    };
+
     if let Ok(mut conn) = pool.get().await {
         let _ = redis::cmd("SET")
             .arg(format!("short:{code}"))
-            .arg(url)
+            .arg(value)
             .arg("EX")
-            .arg(3600)
+            .arg(ttl)
             .query_async::<()>(&mut *conn)
             .await;
 
@@ -23,33 +44,30 @@ pub async fn add_to_cache(pool: &RedisPool, code: &str, url: &str) {
     }
 }
 
-pub async fn get_stats(pool: &RedisPool) -> Option<(i64, i64)> {
+/// Drops a cached link and its rendered QR codes so the next visit reads its current
+/// state from the database
+pub async fn remove_from_cache(pool: &RedisPool, code: &str) {
     if let Ok(mut conn) = pool.get().await {
-        let result = redis::cmd("GET")
-            .arg("stats:global")
-            .query_async(&mut *conn)
+        let _ = redis::cmd("DEL")
+            .arg(format!("short:{code}"))
+            .arg(qr::qr_key(code))
+            .query_async::<()>(&mut *conn)
             .await;
 
-        debug!("Inserted into cache");
-        result.ok()
+        debug!("Removed from cache");
     } else {
-        debug!("Failed to connect to redis pool when inserting");
-        None
+        debug!("Failed to connect to redis pool when removing");
     }
 }
 
-pub async fn set_stats(pool: &RedisPool, total_urls: i64, total_clicks: i64, ttl_seconds: u64) {
-    if let Ok(mut conn) = pool.get().await {
-        let _ = redis::cmd("SET")
-            .arg("stats:global")
-            .arg(format!("{},{}", total_urls, total_clicks))
-            .arg("EX")
-            .arg(ttl_seconds)
-            .query_async::<()>(&mut *conn)
-            .await;
+pub async fn get_from_cache(pool: &RedisPool, code: &str) -> Option<Link> {
+    let mut conn = pool.get().await.ok()?;
+    let value = redis::cmd("GET")
+        .arg(format!("short:{code}"))
+        .query_async::<Option<String>>(&mut *conn)
+        .await
+        .ok()??;
 
-        debug!("Inserted into cache");
-    } else {
-        debug!("Failed to connect to redis pool when inserting");
-    }
+    // Entries written in an older format are treated as a miss and rewritten
+    serde_json::from_str(&value).ok()
 }
@@ -0,0 +1,42 @@
+use super::RedisPool;
+use tracing::debug;
+
+/// Rendered codes only change when the options or the public base URL do
+const QR_TTL_SECS: u64 = 86400;
+
+/// All rendered variants of a link's code share one hash, so they can be dropped together
+pub(super) fn qr_key(code: &str) -> String {
+    format!("qr:{code}")
+}
+
+pub async fn get_qr(pool: &RedisPool, code: &str, variant: &str) -> Option<Vec<u8>> {
+    let mut conn = pool.get().await.ok()?;
+    redis::cmd("HGET")
+        .arg(qr_key(code))
+        .arg(variant)
+        .query_async::<Option<Vec<u8>>>(&mut *conn)
+        .await
+        .ok()?
+}
+
+pub async fn add_qr(pool: &RedisPool, code: &str, variant: &str, image: &[u8]) {
+    let Ok(mut conn) = pool.get().await else {
+        debug!("Failed to connect to redis pool when caching QR code");
+        return;
+    };
+
+    let _ = redis::pipe()
+        .cmd("HSET")
+        .arg(qr_key(code))
+        .arg(variant)
+        .arg(image)
+        .ignore()
+        .cmd("EXPIRE")
+        .arg(qr_key(code))
+        .arg(QR_TTL_SECS)
+        .ignore()
+        .query_async::<()>(&mut *conn)
+        .await;
+
+    debug!("Cached QR code");
+}
@@ -0,0 +1,247 @@
+use super::RedisPool;
+use crate::db::queries::visitors;
+use chrono::{Duration as ChronoDuration, NaiveDate, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::{net::IpAddr, time::Duration};
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+/// Keeps finished days' hyperloglogs around long enough for the nightly job to persist them,
+/// even if it misses a run or two
+const VISITORS_TTL_SECS: u64 = 3 * 86400;
+
+/// Salts expire shortly after their day ends, after which visitor hashes can't be linked back
+const SALT_TTL_SECS: u64 = 2 * 86400;
+
+fn visitors_key(code: &str, date: NaiveDate) -> String {
+    format!("visitors:{code}:{date}")
+}
+
+/// Adds the visitor to today's hyperloglog for the code. The visitor is identified by a hash
+/// of their IP and user agent salted with a per-day random value, so nothing identifying is
+/// stored and visitors can't be correlated across days.
+pub async fn record_visit(pool: &RedisPool, code: &str, ip: IpAddr, user_agent: &str) {
+    let Ok(mut conn) = pool.get().await else {
+        debug!("Failed to connect to redis pool when recording visitor");
+        return;
+    };
+
+    let today = Utc::now().date_naive();
+    let salt_key = format!("visitor_salt:{today}");
+
+    // The first replica to see a new day picks the salt, everyone else reads it back
+    let salt: Option<String> = redis::pipe()
+        .cmd("SET")
+        .arg(&salt_key)
+        .arg(Uuid::new_v4().simple().to_string())
+        .arg("NX")
+        .arg("EX")
+        .arg(SALT_TTL_SECS)
+        .ignore()
+        .cmd("GET")
+        .arg(&salt_key)
+        .query_async::<(Option<String>,)>(&mut *conn)
+        .await
+        .ok()
+        .and_then(|(salt,)| salt);
+
+    let Some(salt) = salt else {
+        debug!("Failed to read visitor salt");
+        return;
+    };
+
+    let visitor = format!(
+        "{:x}",
+        Sha256::new()
+            .chain_update(salt.as_bytes())
+            .chain_update(ip.to_string().as_bytes())
+            .chain_update(user_agent.as_bytes())
+            .finalize()
+    );
+
+    let key = visitors_key(code, today);
+    let _ = redis::pipe()
+        .cmd("PFADD")
+        .arg(&key)
+        .arg(visitor)
+        .ignore()
+        .cmd("EXPIRE")
+        .arg(&key)
+        .arg(VISITORS_TTL_SECS)
+        .ignore()
+        .query_async::<()>(&mut *conn)
+        .await;
+
+    debug!("Recorded visitor");
+}
+
+pub async fn count_visitors(pool: &RedisPool, code: &str, date: NaiveDate) -> Option<i64> {
+    let mut conn = pool.get().await.ok()?;
+    redis::cmd("PFCOUNT")
+        .arg(visitors_key(code, date))
+        .query_async(&mut *conn)
+        .await
+        .ok()
+}
+
+/// Persists the hyperloglog of every day before `today` still held in Redis. Keys live for
+/// `VISITORS_TTL_SECS` past their last visit, so days missed while the service was down
+/// are caught up on the next run; upserting a day again just refreshes its estimate.
+async fn persist_finished_days(
+    redis_pool: &RedisPool,
+    pg_pool: &PgPool,
+    today: NaiveDate,
+) -> usize {
+    let Ok(mut conn) = redis_pool.get().await else {
+        error!("Failed to connect to redis pool when persisting visitors");
+        return 0;
+    };
+
+    let pattern = "visitors:*";
+    let mut keys = Vec::new();
+    let mut cursor = 0u64;
+    loop {
+        let result = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern)
+            .arg("COUNT")
+            .arg(1000)
+            .query_async::<(u64, Vec<String>)>(&mut *conn)
+            .await;
+
+        match result {
+            Ok((next, batch)) => {
+                keys.extend(batch);
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
+            }
+            Err(e) => {
+                error!("Failed to scan visitor keys: {}", e);
+                return 0;
+            }
+        }
+    }
+    drop(conn);
+
+    let mut persisted = 0;
+    for key in keys {
+        let Some((code, date)) = key
+            .strip_prefix("visitors:")
+            .and_then(|rest| rest.rsplit_once(':'))
+            .and_then(|(code, date)| Some((code, date.parse::<NaiveDate>().ok()?)))
+        else {
+            continue;
+        };
+        // Today's count is still growing
+        if date >= today {
+            continue;
+        }
+
+        let Some(count) = count_visitors(redis_pool, code, date).await else {
+            continue;
+        };
+
+        match visitors::upsert_daily(pg_pool, code, date, count).await {
+            Ok(_) => persisted += 1,
+            Err(e) => error!(
+                "Failed to persist unique visitors for {} on {}: {}",
+                code, date, e
+            ),
+        }
+    }
+
+    persisted
+}
+
+/// Persists each day's unique visitor estimates to Postgres shortly after the day ends
+pub fn start_persist_task(redis_pool: RedisPool, pg_pool: PgPool) {
+    tokio::spawn(async move {
+        loop {
+            let today = Utc::now().date_naive();
+            let persisted = persist_finished_days(&redis_pool, &pg_pool, today).await;
+            info!(
+                "Persisted unique visitors for {} code days before {}",
+                persisted, today
+            );
+
+            // Run again a few minutes past the next UTC midnight
+            let next_run = (Utc::now().date_naive() + ChronoDuration::days(1))
+                .and_hms_opt(0, 5, 0)
+                .expect("valid time")
+                .and_utc();
+            let wait = (next_run - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait.max(Duration::from_secs(60))).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::setup_cache;
+
+    /// Runs against the services named by `TEST_REDIS_URL` and `TEST_DATABASE_URL`, the
+    /// database needs `sql/schema.sql` applied
+    #[tokio::test]
+    #[ignore = "needs Redis and Postgres"]
+    async fn missed_days_are_persisted_on_the_next_run() {
+        let redis_pool = setup_cache(&std::env::var("TEST_REDIS_URL").unwrap())
+            .await
+            .unwrap();
+        let pg_pool = PgPool::connect(&std::env::var("TEST_DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+
+        let code = format!("t{}", &Uuid::new_v4().simple().to_string()[..15]);
+        sqlx::query("INSERT INTO urls (code, url, canonical_url) VALUES ($1, $2, $2)")
+            .bind(&code)
+            .bind("https://example.com/")
+            .execute(&pg_pool)
+            .await
+            .unwrap();
+
+        let today = Utc::now().date_naive();
+        let days: Vec<NaiveDate> = (0..3).map(|n| today - ChronoDuration::days(n)).collect();
+        let mut conn = redis_pool.get().await.unwrap();
+        for (visitors, date) in days.iter().enumerate() {
+            let _: () = redis::cmd("PFADD")
+                .arg(visitors_key(&code, *date))
+                .arg((0..=visitors).map(|n| n.to_string()).collect::<Vec<_>>())
+                .query_async(&mut *conn)
+                .await
+                .unwrap();
+        }
+        drop(conn);
+
+        assert!(persist_finished_days(&redis_pool, &pg_pool, today).await >= 2);
+
+        let stored: Vec<(NaiveDate, i64)> = sqlx::query_as(
+            "SELECT day, visitors FROM daily_unique_visitors WHERE code = $1 ORDER BY day",
+        )
+        .bind(&code)
+        .fetch_all(&pg_pool)
+        .await
+        .unwrap();
+        assert_eq!(stored, [(days[2], 3), (days[1], 2)]);
+
+        let mut conn = redis_pool.get().await.unwrap();
+        let _: () = redis::cmd("DEL")
+            .arg(
+                days.iter()
+                    .map(|date| visitors_key(&code, *date))
+                    .collect::<Vec<_>>(),
+            )
+            .query_async(&mut *conn)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM urls WHERE code = $1")
+            .bind(&code)
+            .execute(&pg_pool)
+            .await
+            .unwrap();
+    }
+}
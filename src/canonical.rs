@@ -0,0 +1,213 @@
+//! Canonical form of destination URLs, so spelling variants of the same destination
+//! deduplicate to one code
+
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+use url::{Url, form_urlencoded};
+
+/// Click identifiers added by ad networks and email tools. UTM parameters are left alone
+/// since campaign analytics group links by them.
+const TRACKING_PARAMS: &[&str] = &[
+    "_ga",
+    "_gl",
+    "_hsenc",
+    "_hsmi",
+    "dclid",
+    "fbclid",
+    "gbraid",
+    "gclid",
+    "gclsrc",
+    "igshid",
+    "li_fat_id",
+    "mc_cid",
+    "mc_eid",
+    "mkt_tok",
+    "msclkid",
+    "oly_anon_id",
+    "oly_enc_id",
+    "rb_clickid",
+    "s_cid",
+    "ttclid",
+    "twclid",
+    "vero_id",
+    "wbraid",
+    "wickedid",
+    "yclid",
+];
+
+#[derive(Error, Debug)]
+#[error("Unsupported fragment policy: {0}. Expected keep or strip")]
+pub struct InvalidFragmentPolicy(pub String);
+
+/// Whether URLs that only differ in their `#fragment` count as the same destination
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FragmentPolicy {
+    /// Fragments are significant, e.g. for single page apps routing on them
+    #[default]
+    Keep,
+    /// Fragments are ignored when looking for an existing code
+    Strip,
+}
+
+impl FromStr for FragmentPolicy {
+    type Err = InvalidFragmentPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "keep" => Ok(FragmentPolicy::Keep),
+            "strip" => Ok(FragmentPolicy::Strip),
+            _ => Err(InvalidFragmentPolicy(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for FragmentPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FragmentPolicy::Keep => write!(f, "keep"),
+            FragmentPolicy::Strip => write!(f, "strip"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CanonicalOptions {
+    /// Drop known click identifiers such as `fbclid` and `gclid`
+    pub strip_tracking_params: bool,
+    pub fragment_policy: FragmentPolicy,
+}
+
+/// The form of `url` used to find an existing code. Parsing already lowercases the scheme
+/// and host, converts internationalized hosts to punycode, drops default ports and gives
+/// empty paths a `/`. On top of that a trailing dot on the host, an empty query or fragment,
+/// and optionally tracking parameters and the fragment are removed.
+pub fn canonicalize(url: &str, options: CanonicalOptions) -> Result<String, url::ParseError> {
+    let mut parsed = Url::parse(url)?;
+
+    if let Some(host) = parsed.host_str()
+        && host.len() > 1
+        && host.ends_with('.')
+    {
+        let host = host.trim_end_matches('.').to_string();
+        parsed.set_host(Some(&host))?;
+    }
+
+    if options.strip_tracking_params
+        && let Some(query) = parsed.query()
+    {
+        // Filtering the raw pairs keeps the encoding of everything else untouched
+        let kept: Vec<&str> = query
+            .split('&')
+            .filter(|pair| !pair.is_empty() && !is_tracking_param(pair))
+            .collect();
+        let query = kept.join("&");
+        parsed.set_query(Some(&query));
+    }
+    if parsed.query() == Some("") {
+        parsed.set_query(None);
+    }
+
+    if options.fragment_policy == FragmentPolicy::Strip || parsed.fragment() == Some("") {
+        parsed.set_fragment(None);
+    }
+
+    Ok(parsed.into())
+}
+
+fn is_tracking_param(pair: &str) -> bool {
+    form_urlencoded::parse(pair.as_bytes())
+        .next()
+        .is_some_and(|(key, _)| {
+            TRACKING_PARAMS
+                .iter()
+                .any(|param| key.eq_ignore_ascii_case(param))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canonical(url: &str) -> String {
+        canonicalize(url, CanonicalOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn spelling_variants_share_a_canonical_form() {
+        let expected = "https://example.com/";
+        for url in [
+            "https://example.com",
+            "https://Example.COM/",
+            "HTTPS://example.com:443/",
+            "https://example.com./",
+            "https://example.com/?",
+            "https://example.com/#",
+        ] {
+            assert_eq!(canonical(url), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn internationalized_hosts_become_punycode() {
+        assert_eq!(
+            canonical("https://bücher.example/"),
+            canonical("https://xn--bcher-kva.example/")
+        );
+    }
+
+    #[test]
+    fn paths_queries_and_fragments_stay_significant() {
+        assert_ne!(
+            canonical("https://example.com/a"),
+            canonical("https://example.com/A")
+        );
+        assert_ne!(
+            canonical("https://example.com/?a=1&b=2"),
+            canonical("https://example.com/?b=2&a=1")
+        );
+        assert_ne!(
+            canonical("http://example.com/"),
+            canonical("https://example.com/")
+        );
+        assert_ne!(
+            canonical("https://example.com/#top"),
+            canonical("https://example.com/")
+        );
+    }
+
+    #[test]
+    fn tracking_params_are_stripped_on_request() {
+        let options = CanonicalOptions {
+            strip_tracking_params: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            canonicalize("https://example.com/?fbclid=x&a=%20b&GCLID=y", options).unwrap(),
+            "https://example.com/?a=%20b"
+        );
+        assert_eq!(
+            canonicalize("https://example.com/?fbclid=x", options).unwrap(),
+            "https://example.com/"
+        );
+        assert_eq!(
+            canonicalize("https://example.com/?utm_source=news", options).unwrap(),
+            "https://example.com/?utm_source=news"
+        );
+        assert_eq!(
+            canonical("https://example.com/?fbclid=x"),
+            "https://example.com/?fbclid=x"
+        );
+    }
+
+    #[test]
+    fn fragments_are_stripped_on_request() {
+        let options = CanonicalOptions {
+            fragment_policy: FragmentPolicy::Strip,
+            ..Default::default()
+        };
+        assert_eq!(
+            canonicalize("https://example.com/page#section", options).unwrap(),
+            "https://example.com/page"
+        );
+    }
+}
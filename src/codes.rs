@@ -0,0 +1,522 @@
+//! Short code generation. Codes are drawn at random, derived from a Postgres sequence
+//! through a keyed permutation, or hashed from the destination.
+
+use crate::db::queries::urls;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+/// Longest code the `code` columns can hold
+pub const MAX_CODE_LENGTH: usize = 16;
+pub const MIN_CODE_LENGTH: usize = 4;
+
+const BASE62: &str = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+/// Base62 without `0`, `O`, `I` and `l`
+const BASE58: &str = "123456789abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ";
+/// Crockford's base32 in lowercase, without `i`, `l`, `o` and `u`
+const CROCKFORD32: &str = "0123456789abcdefghjkmnpqrstvwxyz";
+
+/// Collisions in a row after which random and hashed codes get one character longer
+const GROW_AFTER_COLLISIONS: usize = 3;
+const FEISTEL_ROUNDS: u8 = 4;
+
+#[derive(Error, Debug)]
+#[error("Unsupported code strategy: {0}. Expected random, counter or hash")]
+pub struct InvalidCodeStrategy(pub String);
+
+#[derive(Error, Debug)]
+#[error(
+    "Invalid code alphabet: {0}. Expected base62, base58, crockford32 or at least 16 distinct letters, digits, '-' or '_'"
+)]
+pub struct InvalidAlphabet(pub String);
+
+#[derive(Error, Debug)]
+pub enum CodeConfigError {
+    #[error("CODE_LENGTH must be between {MIN_CODE_LENGTH} and {MAX_CODE_LENGTH}, got {0}")]
+    InvalidLength(usize),
+
+    #[error("CODE_SECRET must be set for the counter code strategy")]
+    MissingSecret,
+}
+
+/// How new short codes are picked
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CodeStrategy {
+    /// Random codes, growing by a character once collisions pile up
+    #[default]
+    Random,
+    /// A Postgres sequence shuffled by a keyed permutation, so codes never collide and
+    /// can't be enumerated without the secret
+    Counter,
+    /// Derived from the creator and canonical URL, growing like random codes on collisions
+    Hash,
+}
+
+impl FromStr for CodeStrategy {
+    type Err = InvalidCodeStrategy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "random" => Ok(CodeStrategy::Random),
+            "counter" => Ok(CodeStrategy::Counter),
+            "hash" => Ok(CodeStrategy::Hash),
+            _ => Err(InvalidCodeStrategy(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for CodeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodeStrategy::Random => write!(f, "random"),
+            CodeStrategy::Counter => write!(f, "counter"),
+            CodeStrategy::Hash => write!(f, "hash"),
+        }
+    }
+}
+
+/// Characters codes are made of. Only URL safe characters are accepted, and `+` is
+/// reserved for preview links.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alphabet(Vec<u8>);
+
+impl Alphabet {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Number of distinct codes of `length` characters
+    pub fn space(&self, length: usize) -> u128 {
+        (self.0.len() as u128).pow(length as u32)
+    }
+
+    /// Spells `value` as exactly `length` characters, most significant first
+    fn encode(&self, mut value: u128, length: usize) -> String {
+        let base = self.0.len() as u128;
+        let mut code = vec![self.0[0]; length];
+        for slot in code.iter_mut().rev() {
+            *slot = self.0[(value % base) as usize];
+            value /= base;
+        }
+        code.into_iter().map(char::from).collect()
+    }
+}
+
+impl Default for Alphabet {
+    fn default() -> Self {
+        Alphabet(BASE62.as_bytes().to_vec())
+    }
+}
+
+impl FromStr for Alphabet {
+    type Err = InvalidAlphabet;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chars = match s.trim() {
+            "base62" => BASE62,
+            "base58" => BASE58,
+            "crockford32" => CROCKFORD32,
+            chars => chars,
+        };
+
+        let mut alphabet: Vec<u8> = Vec::with_capacity(chars.len());
+        for c in chars.bytes() {
+            if !(c.is_ascii_alphanumeric() || c == b'-' || c == b'_') || alphabet.contains(&c) {
+                return Err(InvalidAlphabet(s.to_string()));
+            }
+            alphabet.push(c);
+        }
+        // Small alphabets would need codes longer than the columns allow
+        if !(16..=64).contains(&alphabet.len()) {
+            return Err(InvalidAlphabet(s.to_string()));
+        }
+
+        Ok(Alphabet(alphabet))
+    }
+}
+
+impl fmt::Display for Alphabet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.0))
+    }
+}
+
+/// Picks candidate codes with the configured strategy. Inserting a candidate can still
+/// fail on a collision, e.g. with codes left over from another strategy, in which case
+/// callers report it and ask for the next attempt.
+#[derive(Clone, Debug)]
+pub struct CodeGenerator {
+    strategy: CodeStrategy,
+    alphabet: Alphabet,
+    /// Configured length, counter codes only get longer once its space is used up
+    min_length: usize,
+    /// Length of random and hashed codes, grown on repeated collisions and stored in
+    /// Postgres so restarts and other replicas continue at the grown length
+    length: Arc<AtomicUsize>,
+    /// Collisions in a row, reset by every successful code
+    consecutive_collisions: Arc<AtomicUsize>,
+    /// Codes tried and codes found taken since start, indexed by length
+    attempts: Arc<[AtomicU64; MAX_CODE_LENGTH + 1]>,
+    collisions: Arc<[AtomicU64; MAX_CODE_LENGTH + 1]>,
+    key: [u8; 32],
+}
+
+impl CodeGenerator {
+    pub fn new(
+        strategy: CodeStrategy,
+        alphabet: Alphabet,
+        length: usize,
+        secret: Option<&str>,
+    ) -> Result<Self, CodeConfigError> {
+        if !(MIN_CODE_LENGTH..=MAX_CODE_LENGTH).contains(&length) {
+            return Err(CodeConfigError::InvalidLength(length));
+        }
+        // A per process key would shuffle the sequence differently after every restart
+        if strategy == CodeStrategy::Counter && secret.is_none() {
+            return Err(CodeConfigError::MissingSecret);
+        }
+
+        Ok(Self {
+            strategy,
+            alphabet,
+            min_length: length,
+            length: Arc::new(AtomicUsize::new(length)),
+            consecutive_collisions: Arc::new(AtomicUsize::new(0)),
+            attempts: Arc::new(std::array::from_fn(|_| AtomicU64::new(0))),
+            collisions: Arc::new(std::array::from_fn(|_| AtomicU64::new(0))),
+            key: Sha256::digest(secret.unwrap_or_default().as_bytes()).into(),
+        })
+    }
+
+    pub fn strategy(&self) -> CodeStrategy {
+        self.strategy
+    }
+
+    pub fn alphabet(&self) -> &Alphabet {
+        &self.alphabet
+    }
+
+    /// Configured length of new codes
+    pub fn min_length(&self) -> usize {
+        self.min_length
+    }
+
+    /// Key derived from `CODE_SECRET`
+    pub(crate) fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    /// Current length of random and hashed codes
+    pub fn length(&self) -> usize {
+        self.length.load(Ordering::Relaxed)
+    }
+
+    /// Codes of `length` tried and found taken since this process started
+    pub fn attempts(&self, length: usize) -> (u64, u64) {
+        match (self.attempts.get(length), self.collisions.get(length)) {
+            (Some(attempts), Some(collisions)) => (
+                attempts.load(Ordering::Relaxed),
+                collisions.load(Ordering::Relaxed),
+            ),
+            _ => (0, 0),
+        }
+    }
+
+    /// Adopts a longer length stored before a restart or by another replica
+    pub async fn sync_length(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let Some(stored) = urls::get_code_length(pool).await? else {
+            return Ok(());
+        };
+        let stored = (stored.max(0) as usize).min(MAX_CODE_LENGTH);
+        if self.length.fetch_max(stored, Ordering::Relaxed) < stored {
+            info!(
+                "Continuing with {} character codes after earlier collisions",
+                stored
+            );
+        }
+        Ok(())
+    }
+
+    fn record_attempt(&self, code: &str, collided: bool) {
+        if let Some(attempts) = self.attempts.get(code.len()) {
+            attempts.fetch_add(1, Ordering::Relaxed);
+        }
+        if collided && let Some(collisions) = self.collisions.get(code.len()) {
+            collisions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Candidate code for a new link. `seed` identifies the destination for hashed codes,
+    /// and `attempt` counts the collisions of this link so far.
+    pub async fn generate(
+        &self,
+        pool: &PgPool,
+        seed: &str,
+        attempt: usize,
+    ) -> Result<String, sqlx::Error> {
+        match self.strategy {
+            CodeStrategy::Random => Ok(self.random()),
+            CodeStrategy::Counter => {
+                let number = urls::next_code_number(pool).await?;
+                Ok(self.counter(number.max(0) as u128))
+            }
+            CodeStrategy::Hash => Ok(self.hash(seed, attempt)),
+        }
+    }
+
+    /// Up to `count` candidates that don't depend on a destination, for filling the code
+    /// pool. Hashed codes need their destination, so none are generated for them.
+    pub async fn generate_batch(
+        &self,
+        pool: &PgPool,
+        count: usize,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        match self.strategy {
+            CodeStrategy::Random => Ok((0..count).map(|_| self.random()).collect()),
+            CodeStrategy::Counter => {
+                let numbers = urls::next_code_numbers(pool, count as i64).await?;
+                Ok(numbers
+                    .into_iter()
+                    .map(|number| self.counter(number.max(0) as u128))
+                    .collect())
+            }
+            CodeStrategy::Hash => Ok(Vec::new()),
+        }
+    }
+
+    /// Random code of the current length
+    pub fn random(&self) -> String {
+        let length = self.length();
+        let value = rand::rng().random_range(0..self.alphabet.space(length));
+        self.alphabet.encode(value, length)
+    }
+
+    /// Spells the `number`th code. Numbers fill every code of the configured length
+    /// before moving on to one character longer, each length shuffled by its own
+    /// permutation so consecutive numbers look unrelated.
+    fn counter(&self, number: u128) -> String {
+        let (length, index) = self.counter_position(number);
+        let space = self.alphabet.space(length);
+        let value = self.permute(index % space, space, length);
+        self.alphabet.encode(value, length)
+    }
+
+    /// Length of the `number`th counter based code and its index among codes of that length
+    pub fn counter_position(&self, mut number: u128) -> (usize, u128) {
+        let mut length = self.min_length;
+        while number >= self.alphabet.space(length) && length < MAX_CODE_LENGTH {
+            number -= self.alphabet.space(length);
+            length += 1;
+        }
+        (length, number)
+    }
+
+    fn hash(&self, seed: &str, attempt: usize) -> String {
+        let length = self.length();
+        let digest = Sha256::new()
+            .chain_update(self.key)
+            .chain_update(seed.as_bytes())
+            .chain_update((attempt as u64).to_be_bytes())
+            .finalize();
+        let value = u128::from_be_bytes(digest[..16].try_into().expect("digest is 32 bytes"));
+        self.alphabet
+            .encode(value % self.alphabet.space(length), length)
+    }
+
+    /// Keyed Feistel network over the smallest even number of bits covering `space`,
+    /// walking the cycle until the result falls back inside it. This is a bijection on
+    /// `0..space`, so distinct numbers always give distinct codes.
+    fn permute(&self, value: u128, space: u128, length: usize) -> u128 {
+        let bits = 128 - (space - 1).leading_zeros();
+        let half = bits.div_ceil(2);
+        let mask = (1u128 << half) - 1;
+
+        let mut value = value;
+        loop {
+            let (mut left, mut right) = (value >> half, value & mask);
+            for round in 0..FEISTEL_ROUNDS {
+                let next = left ^ (self.round(right, round, length) & mask);
+                left = right;
+                right = next;
+            }
+            value = (left << half) | right;
+            if value < space {
+                return value;
+            }
+        }
+    }
+
+    fn round(&self, half: u128, round: u8, length: usize) -> u128 {
+        let digest = Sha256::new()
+            .chain_update(self.key)
+            .chain_update([round, length as u8])
+            .chain_update(half.to_be_bytes())
+            .finalize();
+        u128::from_be_bytes(digest[..16].try_into().expect("digest is 32 bytes"))
+    }
+
+    /// Records that `code` was already taken. Random and hashed codes grow by a character
+    /// after a few collisions in a row, as that means the current length is filling up.
+    /// Pooled codes were free when reserved and only collide in races, so they never
+    /// grow codes.
+    pub async fn record_collision(&self, pool: &PgPool, code: &str, pooled: bool) {
+        self.record_attempt(code, true);
+        if pooled || self.strategy == CodeStrategy::Counter {
+            return;
+        }
+
+        let collisions = self.consecutive_collisions.fetch_add(1, Ordering::Relaxed) + 1;
+        let length = code.len();
+        if collisions >= GROW_AFTER_COLLISIONS
+            && length < MAX_CODE_LENGTH
+            && self
+                .length
+                .compare_exchange(length, length + 1, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.consecutive_collisions.store(0, Ordering::Relaxed);
+            warn!(
+                "{} code collisions in a row, growing codes to {} characters",
+                collisions,
+                length + 1
+            );
+            if let Err(e) = urls::grow_code_length(pool, (length + 1) as i32).await {
+                error!("Failed to store the grown code length: {}", e);
+            }
+        }
+    }
+
+    pub fn record_success(&self, code: &str) {
+        self.record_attempt(code, false);
+        self.consecutive_collisions.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn generator(strategy: CodeStrategy, alphabet: &str, length: usize) -> CodeGenerator {
+        CodeGenerator::new(strategy, alphabet.parse().unwrap(), length, Some("secret")).unwrap()
+    }
+
+    #[test]
+    fn permutation_is_a_bijection() {
+        let codes = generator(CodeStrategy::Counter, "0123456789abcdef", 4);
+        let space = codes.alphabet().space(4);
+
+        let permuted: HashSet<u128> = (0..space).map(|v| codes.permute(v, space, 4)).collect();
+        assert_eq!(permuted.len() as u128, space);
+        assert!(permuted.iter().all(|&v| v < space));
+    }
+
+    #[test]
+    fn permutation_depends_on_the_secret() {
+        let codes = generator(CodeStrategy::Counter, "base62", 6);
+        let other = CodeGenerator::new(
+            CodeStrategy::Counter,
+            Alphabet::default(),
+            6,
+            Some("other secret"),
+        )
+        .unwrap();
+
+        let first: Vec<String> = (0..10).map(|n| codes.counter(n)).collect();
+        let second: Vec<String> = (0..10).map(|n| other.counter(n)).collect();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn counter_codes_are_distinct_across_lengths() {
+        let codes = generator(CodeStrategy::Counter, "0123456789abcdef", 4);
+        let space = codes.alphabet().space(4);
+
+        let generated: HashSet<String> = (space - 100..space + 100)
+            .map(|n| codes.counter(n))
+            .collect();
+        assert_eq!(generated.len(), 200);
+        assert_eq!(codes.counter(space - 1).len(), 4);
+        assert_eq!(codes.counter(space).len(), 5);
+    }
+
+    #[test]
+    fn counter_position_moves_to_longer_codes_once_a_length_is_used_up() {
+        let codes = generator(CodeStrategy::Counter, "0123456789abcdef", 4);
+        let four = codes.alphabet().space(4);
+        let five = codes.alphabet().space(5);
+
+        assert_eq!(codes.counter_position(0), (4, 0));
+        assert_eq!(codes.counter_position(four - 1), (4, four - 1));
+        assert_eq!(codes.counter_position(four), (5, 0));
+        assert_eq!(codes.counter_position(four + five), (6, 0));
+        assert_eq!(codes.counter_position(u128::MAX).0, MAX_CODE_LENGTH);
+    }
+
+    #[test]
+    fn alphabet_accepts_presets_and_custom_characters() {
+        assert_eq!("base62".parse::<Alphabet>().unwrap(), Alphabet::default());
+        assert_eq!("base58".parse::<Alphabet>().unwrap().len(), 58);
+        assert_eq!(" crockford32 ".parse::<Alphabet>().unwrap().len(), 32);
+        assert_eq!(
+            "0123456789abcdef-_"
+                .parse::<Alphabet>()
+                .unwrap()
+                .to_string(),
+            "0123456789abcdef-_"
+        );
+    }
+
+    #[test]
+    fn alphabet_rejects_invalid_characters() {
+        // too short, duplicated, not URL safe and reserved for preview links
+        assert!("abc".parse::<Alphabet>().is_err());
+        assert!("0123456789abcdeff".parse::<Alphabet>().is_err());
+        assert!("0123456789abcdef/".parse::<Alphabet>().is_err());
+        assert!("0123456789abcdef+".parse::<Alphabet>().is_err());
+        assert!(BASE62.repeat(2).parse::<Alphabet>().is_err());
+    }
+
+    #[test]
+    fn codes_use_only_the_alphabet_and_current_length() {
+        let codes = generator(CodeStrategy::Random, "crockford32", 7);
+        for code in (0..100).map(|_| codes.random()) {
+            assert_eq!(code.len(), 7);
+            assert!(code.bytes().all(|c| CROCKFORD32.as_bytes().contains(&c)));
+        }
+    }
+
+    #[test]
+    fn hashed_codes_are_stable_per_seed_and_attempt() {
+        let codes = generator(CodeStrategy::Hash, "base62", 6);
+        assert_eq!(codes.hash("a", 0), codes.hash("a", 0));
+        assert_ne!(codes.hash("a", 0), codes.hash("a", 1));
+        assert_ne!(codes.hash("a", 0), codes.hash("b", 0));
+    }
+
+    #[test]
+    fn invalid_configurations_are_rejected() {
+        assert!(matches!(
+            CodeGenerator::new(CodeStrategy::Random, Alphabet::default(), 3, None),
+            Err(CodeConfigError::InvalidLength(3))
+        ));
+        assert!(matches!(
+            CodeGenerator::new(CodeStrategy::Counter, Alphabet::default(), 6, None),
+            Err(CodeConfigError::MissingSecret)
+        ));
+    }
+}
@@ -1,5 +1,12 @@
-use std::str::FromStr;
+use crate::{
+    canonical::FragmentPolicy,
+    codes::{Alphabet, CodeStrategy},
+    links::RedirectType,
+};
+use std::{path::PathBuf, str::FromStr};
 use thiserror::Error;
+use tracing::warn;
+use uuid::Uuid;
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
@@ -85,7 +92,41 @@ pub struct Config {
     pub service_port: String,
     pub database_url: String,
     pub stale_urls_days: i32,
+    pub click_retention_days: i32,
     pub cache_url: String,
+    pub client_ip_header: Option<String>,
+    pub geoip_db_path: Option<PathBuf>,
+    /// Destination hosts that can't be shortened, one per line
+    pub domain_blocklist_path: Option<PathBuf>,
+    /// If set, only destination hosts listed here can be shortened
+    pub domain_allowlist_path: Option<PathBuf>,
+    /// Safe Browsing v4 update or plain list of SHA-256 hash prefixes of unsafe URLs
+    pub threat_list_path: Option<PathBuf>,
+    /// Scheme and host short links are served on, e.g. `https://sho.rt`, used in QR codes
+    pub public_base_url: Option<String>,
+    pub default_redirect_type: RedirectType,
+    pub code_strategy: CodeStrategy,
+    /// Length of new codes, random and hashed codes grow past it once collisions pile up
+    pub code_length: usize,
+    pub code_alphabet: Alphabet,
+    /// Keys the permutation of counter based codes and the hash of hashed codes
+    pub code_secret: Option<String>,
+    /// Codes reserved ahead of time in Redis, 0 generates every code on demand
+    pub code_pool_size: usize,
+    /// Chance of a new code colliding above which the code space is reported as saturated
+    pub keyspace_warn_threshold: f64,
+    /// Ignore click identifiers like `fbclid` when looking for an existing code
+    pub strip_tracking_params: bool,
+    /// Whether URLs only differing in their fragment share a code
+    pub url_fragment_policy: FragmentPolicy,
+    /// Signs the cookies that unlock password protected links
+    pub link_cookie_secret: String,
+    /// Salts the hashes reporters are counted by. Falls back to a salt stored in Postgres.
+    pub report_hash_secret: Option<String>,
+    /// Bearer token for the moderation endpoints, which reject every request while unset
+    pub admin_token: Option<String>,
+    /// Open reports from different visitors after which a link is flagged for review
+    pub report_flag_threshold: i64,
     pub redirect_rate_limit_config: RateLimitConfig,
     pub shorten_rate_limit_config: RateLimitConfig,
 }
@@ -99,7 +140,34 @@ impl Config {
             service_port: get_env("SERVICE_PORT").expect("SERVICE_PORT must be set"),
             database_url: get_env("DATABASE_URL").expect("DATABASE_URL must be set"),
             stale_urls_days: get_env("STALE_URLS_DAYS").unwrap_or(90),
+            click_retention_days: get_env("CLICK_RETENTION_DAYS").unwrap_or(90),
             cache_url: get_env("CACHE_URL").expect("CACHE_URL must be set"),
+            client_ip_header: get_env("CLIENT_IP_HEADER"),
+            geoip_db_path: get_env("GEOIP_DB_PATH"),
+            domain_blocklist_path: get_env("DOMAIN_BLOCKLIST_PATH"),
+            domain_allowlist_path: get_env("DOMAIN_ALLOWLIST_PATH"),
+            threat_list_path: get_env("THREAT_LIST_PATH"),
+            public_base_url: get_env("PUBLIC_BASE_URL"),
+            default_redirect_type: get_env("DEFAULT_REDIRECT_TYPE")
+                .unwrap_or(RedirectType::TemporaryRedirect),
+            code_strategy: get_env("CODE_STRATEGY").unwrap_or_default(),
+            code_length: get_env("CODE_LENGTH").unwrap_or(6),
+            code_alphabet: get_env("CODE_ALPHABET").unwrap_or_default(),
+            code_secret: get_env::<String>("CODE_SECRET").filter(|secret| !secret.is_empty()),
+            code_pool_size: get_env("CODE_POOL_SIZE").unwrap_or(1000),
+            keyspace_warn_threshold: get_env("KEYSPACE_WARN_THRESHOLD").unwrap_or(0.01),
+            strip_tracking_params: get_env("STRIP_TRACKING_PARAMS").unwrap_or(false),
+            url_fragment_policy: get_env("URL_FRAGMENT_POLICY").unwrap_or_default(),
+            link_cookie_secret: get_env("LINK_COOKIE_SECRET").unwrap_or_else(|| {
+                warn!(
+                    "LINK_COOKIE_SECRET is not set, unlocked links will ask for their password again after a restart or on another replica"
+                );
+                Uuid::new_v4().simple().to_string()
+            }),
+            report_hash_secret: get_env::<String>("REPORT_HASH_SECRET")
+                .filter(|secret| !secret.is_empty()),
+            admin_token: get_env::<String>("ADMIN_TOKEN").filter(|token| !token.is_empty()),
+            report_flag_threshold: get_env("REPORT_FLAG_THRESHOLD").unwrap_or(3),
             redirect_rate_limit_config: RateLimitConfig::from_env_or_default(
                 "REDIRECT_RATE_LIMIT",
                 "20:30:60",
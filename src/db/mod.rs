@@ -1,16 +1,25 @@
 pub mod queries;
 
 use crate::sql_query;
-use sqlx::postgres::{PgPool, PgPoolOptions};
+use queries::{clicks, rollups};
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
 use std::time::Duration;
 use tracing::{error, info};
 
+/// Number of monthly click partitions kept ready beyond the current month
+const CLICK_PARTITIONS_AHEAD: i32 = 3;
+
 /// PostgreSQL unique constraint violation error code
 /// Reference: https://www.postgresql.org/docs/current/errcodes-appendix.html
 pub const PG_UNIQUE_VIOLATION: &str = "23505";
 
+/// Sessions run in UTC, so click timestamps and the days rollups bucket them into agree
+/// with the UTC days unique visitors are counted for, whatever the server's time zone
 pub async fn setup_database(url: &str) -> Result<PgPool, sqlx::Error> {
-    PgPoolOptions::new().connect(url).await
+    let options: PgConnectOptions = url.parse()?;
+    PgPoolOptions::new()
+        .connect_with(options.options([("timezone", "UTC")]))
+        .await
 }
 
 pub fn is_collision(db_err: &dyn sqlx::error::DatabaseError) -> bool {
@@ -40,6 +49,42 @@ pub fn start_cleanup_task(pool: PgPool, stale_urls_days: i32) {
     });
 }
 
+pub fn start_partition_task(pool: PgPool, click_retention_days: i32) {
+    tokio::spawn(async move {
+        loop {
+            match clicks::create_partitions(&pool, CLICK_PARTITIONS_AHEAD).await {
+                Ok(created) => info!("Created {} click partitions", created),
+                Err(e) => error!("Error creating click partitions: {}", e),
+            }
+
+            match clicks::drop_expired_partitions(&pool, click_retention_days).await {
+                Ok(dropped) => info!("Dropped {} expired click partitions", dropped),
+                Err(e) => error!("Error dropping expired click partitions: {}", e),
+            }
+
+            tokio::time::sleep(Duration::from_secs(86400)).await; // Daily
+        }
+    });
+}
+
+pub fn start_rollup_task(pool: PgPool, click_retention_days: i32) {
+    tokio::spawn(async move {
+        loop {
+            match rollups::run(&pool).await {
+                Ok(rolled_up) => info!("Rolled up {} clicks", rolled_up),
+                Err(e) => error!("Error rolling up clicks: {}", e),
+            }
+
+            match rollups::prune_hourly(&pool, click_retention_days).await {
+                Ok(rows) => info!("Pruned {} hourly rollups past retention", rows),
+                Err(e) => error!("Error pruning hourly rollups: {}", e),
+            }
+
+            tokio::time::sleep(Duration::from_secs(3600)).await; // Hourly
+        }
+    });
+}
+
 #[macro_export]
 macro_rules! sql_query {
     ($module:literal, $file:literal) => {
@@ -1,22 +1,25 @@
 pub mod urls {
-    use crate::sql_query;
-    use sqlx::{PgPool, postgres::PgQueryResult};
+    use crate::{
+        links::{Link, moderation::LinkStatus},
+        sql_query,
+    };
+    use sqlx::{PgPool, postgres::PgQueryResult, types::chrono::NaiveDateTime};
 
-    pub async fn find_url_by_code(
-        pool: &PgPool,
-        code: &str,
-    ) -> Result<Option<String>, sqlx::Error> {
-        let stmt = sql_query!("urls", "find_url_by_code");
-        sqlx::query_scalar(stmt)
-            .bind(code)
-            .fetch_optional(pool)
-            .await
+    pub async fn find_link_by_code(pool: &PgPool, code: &str) -> Result<Option<Link>, sqlx::Error> {
+        let stmt = sql_query!("urls", "find_link_by_code");
+        sqlx::query_as(stmt).bind(code).fetch_optional(pool).await
     }
 
-    pub async fn find_code_by_url(pool: &PgPool, url: &str) -> Result<Option<String>, sqlx::Error> {
+    /// Looks up a creator's shareable link by the canonical form of its destination
+    pub async fn find_code_by_url(
+        pool: &PgPool,
+        url: &str,
+        creator_id: Option<&str>,
+    ) -> Result<Option<(String, LinkStatus)>, sqlx::Error> {
         let stmt = sql_query!("urls", "find_code_by_url");
-        sqlx::query_scalar(stmt)
+        sqlx::query_as(stmt)
             .bind(url)
+            .bind(creator_id)
             .fetch_optional(pool)
             .await
     }
@@ -24,10 +27,96 @@ pub mod urls {
     pub async fn insert(
         pool: &PgPool,
         code: &str,
-        url: &str,
+        link: &Link,
+        canonical_url: &str,
+        creator_id: Option<&str>,
+        dedupe: bool,
+        utm_campaign: Option<&str>,
     ) -> Result<PgQueryResult, sqlx::Error> {
         let stmt = sql_query!("urls", "insert");
-        sqlx::query(stmt).bind(code).bind(url).execute(pool).await
+        sqlx::query(stmt)
+            .bind(code)
+            .bind(&link.url)
+            .bind(link.redirect_type)
+            .bind(link.forward_query)
+            .bind(link.forward_path)
+            .bind(link.query_conflict)
+            .bind(utm_campaign)
+            .bind(link.rules.iter().map(|r| r.kind).collect::<Vec<_>>())
+            .bind(link.rules.iter().map(|r| &r.value).collect::<Vec<_>>())
+            .bind(link.rules.iter().map(|r| &r.url).collect::<Vec<_>>())
+            .bind(link.variants.iter().map(|v| &v.label).collect::<Vec<_>>())
+            .bind(link.variants.iter().map(|v| &v.url).collect::<Vec<_>>())
+            .bind(link.variants.iter().map(|v| v.weight).collect::<Vec<_>>())
+            .bind(link.not_before)
+            .bind(link.not_after)
+            .bind(
+                link.schedule
+                    .iter()
+                    .map(|s| s.starts_at)
+                    .collect::<Vec<_>>(),
+            )
+            .bind(link.schedule.iter().map(|s| &s.url).collect::<Vec<_>>())
+            .bind(&link.password_hash)
+            .bind(link.single_use)
+            .bind(link.interstitial)
+            .bind(canonical_url)
+            .bind(creator_id)
+            .bind(dedupe)
+            .execute(pool)
+            .await
+    }
+
+    /// Next value of the sequence behind counter based codes, starting at 0
+    pub async fn next_code_number(pool: &PgPool) -> Result<i64, sqlx::Error> {
+        let stmt = sql_query!("urls", "next_code_number");
+        sqlx::query_scalar(stmt).fetch_one(pool).await
+    }
+
+    /// The number the next counter based code will get, without taking it
+    pub async fn peek_code_number(pool: &PgPool) -> Result<i64, sqlx::Error> {
+        let stmt = sql_query!("urls", "peek_code_number");
+        sqlx::query_scalar(stmt).fetch_one(pool).await
+    }
+
+    /// Length random and hashed codes grew to, if they ever did
+    pub async fn get_code_length(pool: &PgPool) -> Result<Option<i32>, sqlx::Error> {
+        let stmt = sql_query!("urls", "get_code_length");
+        sqlx::query_scalar(stmt).fetch_optional(pool).await
+    }
+
+    pub async fn grow_code_length(
+        pool: &PgPool,
+        length: i32,
+    ) -> Result<PgQueryResult, sqlx::Error> {
+        let stmt = sql_query!("urls", "grow_code_length");
+        sqlx::query(stmt).bind(length).execute(pool).await
+    }
+
+    pub async fn next_code_numbers(pool: &PgPool, count: i64) -> Result<Vec<i64>, sqlx::Error> {
+        let stmt = sql_query!("urls", "next_code_numbers");
+        sqlx::query_scalar(stmt).bind(count).fetch_all(pool).await
+    }
+
+    /// The given codes that no link uses yet
+    pub async fn filter_unused_codes(
+        pool: &PgPool,
+        codes: &[String],
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let stmt = sql_query!("urls", "filter_unused_codes");
+        sqlx::query_scalar(stmt).bind(codes).fetch_all(pool).await
+    }
+
+    pub async fn find_campaign_by_code(
+        pool: &PgPool,
+        code: &str,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let stmt = sql_query!("urls", "find_campaign_by_code");
+        let campaign: Option<Option<String>> = sqlx::query_scalar(stmt)
+            .bind(code)
+            .fetch_optional(pool)
+            .await?;
+        Ok(campaign.flatten())
     }
 
     pub async fn list_all(pool: &PgPool) -> Result<Vec<(String, String)>, sqlx::Error> {
@@ -40,6 +129,59 @@ pub mod urls {
         sqlx::query(stmt).execute(pool).await
     }
 
+    pub async fn find_created_at(
+        pool: &PgPool,
+        code: &str,
+    ) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+        let stmt = sql_query!("urls", "find_created_at");
+        sqlx::query_scalar(stmt)
+            .bind(code)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Marks a single use link as followed, returning whether this call was the one to do it
+    pub async fn consume(pool: &PgPool, code: &str) -> Result<bool, sqlx::Error> {
+        let stmt = sql_query!("urls", "consume");
+        let consumed: Option<String> = sqlx::query_scalar(stmt)
+            .bind(code)
+            .fetch_optional(pool)
+            .await?;
+        Ok(consumed.is_some())
+    }
+
+    /// Codes after `after` with every destination they can redirect to, skipping links
+    /// already disabled by the threat list
+    pub async fn list_destinations(
+        pool: &PgPool,
+        after: &str,
+        limit: i64,
+    ) -> Result<Vec<(String, Vec<String>)>, sqlx::Error> {
+        let stmt = sql_query!("urls", "list_destinations");
+        sqlx::query_as(stmt)
+            .bind(after)
+            .bind(limit)
+            .fetch_all(pool)
+            .await
+    }
+
+    /// Disables links whose destination matched the threat list, returning the newly
+    /// disabled codes
+    pub async fn flag_threats(pool: &PgPool, codes: &[String]) -> Result<Vec<String>, sqlx::Error> {
+        let stmt = sql_query!("urls", "flag_threats");
+        sqlx::query_scalar(stmt).bind(codes).fetch_all(pool).await
+    }
+
+    /// Flags links whose destination matched only a threat list hash prefix and opens a
+    /// report for them, returning the newly flagged codes
+    pub async fn flag_threat_reviews(
+        pool: &PgPool,
+        codes: &[String],
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let stmt = sql_query!("urls", "flag_threat_reviews");
+        sqlx::query_scalar(stmt).bind(codes).fetch_all(pool).await
+    }
+
     pub async fn delete_code(pool: &PgPool, code: &str) -> Result<Option<String>, sqlx::Error> {
         let stmt = sql_query!("urls", "delete_code");
         sqlx::query_scalar(stmt)
@@ -52,41 +194,382 @@ pub mod urls {
 pub mod clicks {
     use crate::sql_query;
     use serde::Serialize;
-    use sqlx::{PgPool, postgres::PgQueryResult, types::chrono::NaiveDate};
+    use sqlx::{
+        PgPool,
+        postgres::PgQueryResult,
+        types::chrono::{NaiveDate, NaiveDateTime},
+    };
     use utoipa::ToSchema;
 
-    pub async fn insert(pool: &PgPool, code: &str) -> Result<PgQueryResult, sqlx::Error> {
+    pub async fn insert(
+        pool: &PgPool,
+        code: &str,
+        referrer_host: Option<&str>,
+        country: Option<&str>,
+        variant: Option<&str>,
+        is_bot: bool,
+    ) -> Result<PgQueryResult, sqlx::Error> {
         let stmt = sql_query!("clicks", "insert");
-        sqlx::query(stmt).bind(code).execute(pool).await
+        sqlx::query(stmt)
+            .bind(code)
+            .bind(referrer_host)
+            .bind(country)
+            .bind(variant)
+            .bind(is_bot)
+            .execute(pool)
+            .await
     }
 
-    pub async fn get_code_total_clicks(pool: &PgPool, code: &str) -> Result<i64, sqlx::Error> {
+    pub async fn get_code_total_clicks(
+        pool: &PgPool,
+        code: &str,
+        include_bots: bool,
+    ) -> Result<i64, sqlx::Error> {
         let stmt = sql_query!("clicks", "get_code_total_clicks");
-        sqlx::query_scalar(stmt).bind(code).fetch_one(pool).await
+        sqlx::query_scalar(stmt)
+            .bind(code)
+            .bind(include_bots)
+            .fetch_one(pool)
+            .await
     }
 
     #[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
     pub struct DailyClick {
-        date: NaiveDate,
+        pub(crate) date: NaiveDate,
         count: i64,
+        /// Estimated unique visitors for the day
+        pub(crate) unique_visitors: Option<i64>,
     }
 
     pub async fn get_code_daily_clicks(
         pool: &PgPool,
         code: &str,
+        include_bots: bool,
     ) -> Result<Vec<DailyClick>, sqlx::Error> {
         let stmt = sql_query!("clicks", "get_code_daily_clicks");
-        sqlx::query_as(stmt).bind(code).fetch_all(pool).await
+        sqlx::query_as(stmt)
+            .bind(code)
+            .bind(include_bots)
+            .fetch_all(pool)
+            .await
+    }
+
+    #[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+    pub struct HourlyClick {
+        hour: NaiveDateTime,
+        count: i64,
+    }
+
+    pub async fn get_code_hourly_clicks(
+        pool: &PgPool,
+        code: &str,
+        include_bots: bool,
+    ) -> Result<Vec<HourlyClick>, sqlx::Error> {
+        let stmt = sql_query!("clicks", "get_code_hourly_clicks");
+        sqlx::query_as(stmt)
+            .bind(code)
+            .bind(include_bots)
+            .fetch_all(pool)
+            .await
+    }
+
+    #[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+    pub struct ReferrerClicks {
+        referrer_host: String,
+        count: i64,
+    }
+
+    pub async fn get_code_referrers(
+        pool: &PgPool,
+        code: &str,
+        include_bots: bool,
+    ) -> Result<Vec<ReferrerClicks>, sqlx::Error> {
+        let stmt = sql_query!("clicks", "get_code_referrers");
+        sqlx::query_as(stmt)
+            .bind(code)
+            .bind(include_bots)
+            .fetch_all(pool)
+            .await
+    }
+
+    #[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+    pub struct CountryClicks {
+        /// ISO 3166-1 alpha-2 code, empty when the client IP could not be resolved
+        country: String,
+        count: i64,
+    }
+
+    pub async fn get_code_countries(
+        pool: &PgPool,
+        code: &str,
+        include_bots: bool,
+    ) -> Result<Vec<CountryClicks>, sqlx::Error> {
+        let stmt = sql_query!("clicks", "get_code_countries");
+        sqlx::query_as(stmt)
+            .bind(code)
+            .bind(include_bots)
+            .fetch_all(pool)
+            .await
+    }
+
+    #[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+    pub struct VariantClicks {
+        variant: String,
+        count: i64,
+    }
+
+    pub async fn get_code_variants(
+        pool: &PgPool,
+        code: &str,
+        include_bots: bool,
+    ) -> Result<Vec<VariantClicks>, sqlx::Error> {
+        let stmt = sql_query!("clicks", "get_code_variants");
+        sqlx::query_as(stmt)
+            .bind(code)
+            .bind(include_bots)
+            .fetch_all(pool)
+            .await
+    }
+
+    /// Creates the monthly partitions for the current month and the next `months_ahead` months
+    pub async fn create_partitions(pool: &PgPool, months_ahead: i32) -> Result<i32, sqlx::Error> {
+        let stmt = sql_query!("clicks", "create_partitions");
+        sqlx::query_scalar(stmt)
+            .bind(months_ahead)
+            .fetch_one(pool)
+            .await
+    }
+
+    /// Drops monthly partitions whose clicks are all past retention and already rolled up
+    pub async fn drop_expired_partitions(
+        pool: &PgPool,
+        retention_days: i32,
+    ) -> Result<i32, sqlx::Error> {
+        let stmt = sql_query!("clicks", "drop_expired_partitions");
+        sqlx::query_scalar(stmt)
+            .bind(retention_days)
+            .fetch_one(pool)
+            .await
+    }
+}
+
+pub mod rollups {
+    use crate::sql_query;
+    use sqlx::PgPool;
+
+    /// Aggregates raw clicks up to the last full hour, returning the number of clicks rolled up
+    pub async fn run(pool: &PgPool) -> Result<i64, sqlx::Error> {
+        let stmt = sql_query!("rollups", "run");
+        sqlx::query_scalar(stmt).fetch_one(pool).await
+    }
+
+    pub async fn prune_hourly(pool: &PgPool, retention_days: i32) -> Result<u64, sqlx::Error> {
+        let stmt = sql_query!("rollups", "prune_hourly");
+        let result = sqlx::query(stmt).bind(retention_days).execute(pool).await?;
+        Ok(result.rows_affected())
+    }
+}
+
+pub mod visitors {
+    use crate::sql_query;
+    use sqlx::{PgPool, postgres::PgQueryResult, types::chrono::NaiveDate};
+
+    pub async fn upsert_daily(
+        pool: &PgPool,
+        code: &str,
+        day: NaiveDate,
+        visitors: i64,
+    ) -> Result<PgQueryResult, sqlx::Error> {
+        let stmt = sql_query!("visitors", "upsert_daily");
+        sqlx::query(stmt)
+            .bind(code)
+            .bind(day)
+            .bind(visitors)
+            .execute(pool)
+            .await
+    }
+}
+
+pub mod moderation {
+    use crate::{
+        links::moderation::{DisableReason, LinkStatus, ReportReason},
+        sql_query,
+    };
+    use chrono::{DateTime, Utc};
+    use serde::Serialize;
+    use sqlx::{PgPool, postgres::PgQueryResult};
+    use utoipa::ToSchema;
+
+    /// Records a report unless the same reporter already has an open report for the link
+    pub async fn insert_report(
+        pool: &PgPool,
+        code: &str,
+        reason: ReportReason,
+        details: Option<&str>,
+        reporter: &str,
+    ) -> Result<PgQueryResult, sqlx::Error> {
+        let stmt = sql_query!("moderation", "insert_report");
+        sqlx::query(stmt)
+            .bind(code)
+            .bind(reason)
+            .bind(details)
+            .bind(reporter)
+            .execute(pool)
+            .await
+    }
+
+    /// Flags an active link once it has `threshold` open reports, returning whether it was
+    /// flagged by this call
+    pub async fn flag_reported(
+        pool: &PgPool,
+        code: &str,
+        threshold: i64,
+    ) -> Result<bool, sqlx::Error> {
+        let stmt = sql_query!("moderation", "flag_reported");
+        let flagged: Option<String> = sqlx::query_scalar(stmt)
+            .bind(code)
+            .bind(threshold)
+            .fetch_optional(pool)
+            .await?;
+        Ok(flagged.is_some())
+    }
+
+    /// The stored salt for reporter hashes, storing `candidate` if there is none yet
+    pub async fn report_hash_salt(pool: &PgPool, candidate: &str) -> Result<String, sqlx::Error> {
+        let stmt = sql_query!("moderation", "report_hash_salt");
+        sqlx::query_scalar(stmt)
+            .bind(candidate)
+            .fetch_one(pool)
+            .await
+    }
+
+    #[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+    pub struct ReportedLink {
+        code: String,
+        url: String,
+        status: LinkStatus,
+        /// Set when a destination matched the threat list
+        threat_detected_at: Option<DateTime<Utc>>,
+        open_reports: i64,
+        reasons: Vec<ReportReason>,
+        /// Up to five of the most recent free text details
+        details: Option<Vec<String>>,
+        first_reported_at: DateTime<Utc>,
+        last_reported_at: DateTime<Utc>,
+    }
+
+    /// Links with open reports, most reported first
+    pub async fn list_reported(
+        pool: &PgPool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<ReportedLink>, sqlx::Error> {
+        let stmt = sql_query!("moderation", "list_reported");
+        sqlx::query_as(stmt)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(pool)
+            .await
+    }
+
+    /// Returns whether the link exists
+    pub async fn set_status(
+        pool: &PgPool,
+        code: &str,
+        status: LinkStatus,
+        disabled_reason: Option<DisableReason>,
+    ) -> Result<bool, sqlx::Error> {
+        let stmt = sql_query!("moderation", "set_status");
+        let updated: Option<String> = sqlx::query_scalar(stmt)
+            .bind(code)
+            .bind(status)
+            .bind(disabled_reason)
+            .fetch_optional(pool)
+            .await?;
+        Ok(updated.is_some())
+    }
+
+    pub async fn resolve_reports(pool: &PgPool, code: &str) -> Result<PgQueryResult, sqlx::Error> {
+        let stmt = sql_query!("moderation", "resolve_reports");
+        sqlx::query(stmt).bind(code).execute(pool).await
+    }
+}
+
+pub mod utm {
+    use crate::{
+        sql_query,
+        utm::{UtmParams, UtmTemplate},
+    };
+    use sqlx::{PgPool, postgres::PgQueryResult};
+
+    pub async fn insert_template(
+        pool: &PgPool,
+        name: &str,
+        params: &UtmParams,
+    ) -> Result<PgQueryResult, sqlx::Error> {
+        let stmt = sql_query!("utm", "insert_template");
+        sqlx::query(stmt)
+            .bind(name)
+            .bind(&params.source)
+            .bind(&params.medium)
+            .bind(&params.campaign)
+            .bind(&params.term)
+            .bind(&params.content)
+            .execute(pool)
+            .await
+    }
+
+    pub async fn find_template(
+        pool: &PgPool,
+        name: &str,
+    ) -> Result<Option<UtmTemplate>, sqlx::Error> {
+        let stmt = sql_query!("utm", "find_template");
+        sqlx::query_as(stmt).bind(name).fetch_optional(pool).await
+    }
+
+    pub async fn list_templates(pool: &PgPool) -> Result<Vec<UtmTemplate>, sqlx::Error> {
+        let stmt = sql_query!("utm", "list_templates");
+        sqlx::query_as(stmt).fetch_all(pool).await
     }
 }
 
 pub mod stats {
     use crate::sql_query;
+    use serde::Serialize;
     use sqlx::PgPool;
+    use utoipa::ToSchema;
 
-    pub async fn get_total_counts(pool: &PgPool) -> Result<(i64, i64), sqlx::Error> {
+    /// Returns the total urls, human clicks and bot clicks
+    pub async fn get_total_counts(pool: &PgPool) -> Result<(i64, i64, i64), sqlx::Error> {
         let stmt = sql_query!("stats", "get_stats");
-        let result: (i64, i64) = sqlx::query_as(stmt).fetch_one(pool).await?;
+        let result: (i64, i64, i64) = sqlx::query_as(stmt).fetch_one(pool).await?;
         Ok(result)
     }
+
+    /// Number of links per code length
+    pub async fn get_code_lengths(pool: &PgPool) -> Result<Vec<(i32, i64)>, sqlx::Error> {
+        let stmt = sql_query!("stats", "get_code_lengths");
+        sqlx::query_as(stmt).fetch_all(pool).await
+    }
+
+    #[derive(Serialize, Debug, sqlx::FromRow, ToSchema)]
+    pub struct CampaignStats {
+        campaign: String,
+        total_urls: i64,
+        total_clicks: i64,
+    }
+
+    /// Campaigns with the most clicks, read from the trigger maintained campaign counters
+    pub async fn get_campaign_stats(
+        pool: &PgPool,
+        include_bots: bool,
+        limit: i64,
+    ) -> Result<Vec<CampaignStats>, sqlx::Error> {
+        let stmt = sql_query!("stats", "get_campaign_stats");
+        sqlx::query_as(stmt)
+            .bind(include_bots)
+            .bind(limit)
+            .fetch_all(pool)
+            .await
+    }
 }
@@ -0,0 +1,139 @@
+use crate::watch::WatchedFile;
+use std::{collections::HashSet, path::Path};
+use tracing::{info, warn};
+use url::Host;
+
+/// Outcome of checking a destination host against the lists
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    Blocked,
+    NotAllowed,
+}
+
+/// A set of host patterns read from a file with one entry per line. `example.com` matches
+/// only that host, `*.example.com` matches every subdomain of it. Blank lines and lines
+/// starting with `#` are ignored.
+#[derive(Debug, Default)]
+struct DomainList {
+    hosts: HashSet<String>,
+    wildcards: HashSet<String>,
+}
+
+impl DomainList {
+    fn parse(contents: &str) -> Self {
+        let mut list = Self::default();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (wildcard, pattern) = match line.strip_prefix("*.") {
+                Some(domain) => (true, domain),
+                None => (false, line),
+            };
+            // Normalizes case and internationalized names the same way the url crate does
+            let Some(host) = Host::parse(pattern.trim_end_matches('.'))
+                .ok()
+                .map(|host| host.to_string())
+            else {
+                warn!("Ignoring invalid domain list entry: {}", line);
+                continue;
+            };
+
+            if wildcard {
+                list.wildcards.insert(host);
+            } else {
+                list.hosts.insert(host);
+            }
+        }
+        list
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        if self.hosts.contains(host) {
+            return true;
+        }
+
+        let mut parent = host;
+        while let Some((_, rest)) = parent.split_once('.') {
+            if self.wildcards.contains(rest) {
+                return true;
+            }
+            parent = rest;
+        }
+        false
+    }
+
+    fn len(&self) -> usize {
+        self.hosts.len() + self.wildcards.len()
+    }
+}
+
+/// Decides which destination hosts may be shortened, from an optional blocklist and an
+/// optional allowlist. The blocklist always wins, and once an allowlist is configured
+/// only hosts on it are accepted.
+#[derive(Clone, Default)]
+pub struct DomainPolicy {
+    blocked: Option<WatchedFile<DomainList>>,
+    /// `None` allows every host that isn't blocked
+    allowed: Option<WatchedFile<DomainList>>,
+}
+
+impl DomainPolicy {
+    /// Loads the configured lists. An unreadable blocklist blocks nothing, while an
+    /// unreadable allowlist allows nothing, until the reload task picks up a valid file.
+    pub fn open(blocklist: Option<&Path>, allowlist: Option<&Path>) -> Self {
+        Self {
+            blocked: blocklist.map(|path| WatchedFile::open(path, read_list)),
+            allowed: allowlist.map(|path| WatchedFile::open(path, read_list)),
+        }
+    }
+
+    /// Checks a lowercase host as returned by `Url::host_str`
+    pub fn check(&self, host: &str) -> Verdict {
+        let host = host.trim_end_matches('.');
+
+        if self
+            .blocked
+            .as_ref()
+            .and_then(|list| list.read(|list| list.matches(host)))
+            .unwrap_or(false)
+        {
+            Verdict::Blocked
+        } else if self
+            .allowed
+            .as_ref()
+            .is_some_and(|list| !list.read(|list| list.matches(host)).unwrap_or(false))
+        {
+            Verdict::NotAllowed
+        } else {
+            Verdict::Allowed
+        }
+    }
+
+    /// Reloads a list whenever its file changes, so entries can be added without a restart
+    pub fn start_reload_task(&self) {
+        for list in self.blocked.iter().chain(&self.allowed) {
+            list.start_reload_task();
+        }
+    }
+}
+
+fn read_list(path: &Path) -> Option<DomainList> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => {
+            let list = DomainList::parse(&contents);
+            info!(
+                "Loaded {} domain entries from {}",
+                list.len(),
+                path.display()
+            );
+            Some(list)
+        }
+        Err(e) => {
+            warn!("Failed to load domain list {}: {}", path.display(), e);
+            None
+        }
+    }
+}
@@ -17,9 +17,60 @@ pub enum ApiError {
     #[error("Unsupported URL scheme: {scheme}. Only http/https allowed")]
     UnsupportedScheme { scheme: String },
 
+    #[error("Destination not allowed: {host}")]
+    BlockedDestination { host: String },
+
+    #[error("Destination is flagged as unsafe: {host}")]
+    UnsafeDestination { host: String },
+
     #[error("URL not found")]
     NotFound,
 
+    #[error("Invalid report: {reason}")]
+    InvalidReport { reason: String },
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    #[error("Link has ended")]
+    LinkEnded,
+
+    #[error("Link has already been used")]
+    LinkConsumed,
+
+    #[error("UTM template not found: {name}")]
+    UtmTemplateNotFound { name: String },
+
+    #[error("UTM template already exists: {name}")]
+    UtmTemplateExists { name: String },
+
+    #[error("Invalid UTM template: {reason}")]
+    InvalidUtmTemplate { reason: String },
+
+    #[error("Invalid creator id, expected 1-64 letters, digits, '.', '-' or '_'")]
+    InvalidCreatorId,
+
+    #[error("Invalid targeting rule: {reason}")]
+    InvalidRule { reason: String },
+
+    #[error("Invalid A/B variant: {reason}")]
+    InvalidVariant { reason: String },
+
+    #[error("Invalid schedule: {reason}")]
+    InvalidSchedule { reason: String },
+
+    #[error("Password must be between 1 and {max} characters")]
+    InvalidPassword { max: usize },
+
+    #[error("Failed to hash password")]
+    PasswordHashFailed,
+
+    #[error("Invalid QR code options: {reason}")]
+    InvalidQrOptions { reason: String },
+
+    #[error("Failed to render QR code: {0}")]
+    QrRender(#[from] crate::qr::QrError),
+
     #[error("Maximum collision retries exceeded")]
     TooManyCollisions,
 
@@ -47,7 +98,65 @@ impl IntoResponse for ApiError {
                 StatusCode::BAD_REQUEST,
                 format!("Unsupported URL scheme: {scheme}"),
             ),
+            ApiError::BlockedDestination { host } => (
+                StatusCode::FORBIDDEN,
+                format!("Destination not allowed: {host}"),
+            ),
+            ApiError::UnsafeDestination { host } => (
+                StatusCode::FORBIDDEN,
+                format!("Destination is flagged as unsafe: {host}"),
+            ),
             ApiError::NotFound => (StatusCode::NOT_FOUND, "URL not found".to_string()),
+            ApiError::InvalidReport { reason } => {
+                (StatusCode::BAD_REQUEST, format!("Invalid report: {reason}"))
+            }
+            ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
+            ApiError::LinkEnded => (StatusCode::GONE, "Link has ended".to_string()),
+            ApiError::LinkConsumed => (StatusCode::GONE, "Link has already been used".to_string()),
+            ApiError::UtmTemplateNotFound { name } => (
+                StatusCode::BAD_REQUEST,
+                format!("UTM template not found: {name}"),
+            ),
+            ApiError::UtmTemplateExists { name } => (
+                StatusCode::CONFLICT,
+                format!("UTM template already exists: {name}"),
+            ),
+            ApiError::InvalidUtmTemplate { reason } => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid UTM template: {reason}"),
+            ),
+            ApiError::InvalidCreatorId => (
+                StatusCode::BAD_REQUEST,
+                "Invalid creator id, expected 1-64 letters, digits, '.', '-' or '_'".to_string(),
+            ),
+            ApiError::InvalidRule { reason } => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid targeting rule: {reason}"),
+            ),
+            ApiError::InvalidVariant { reason } => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid A/B variant: {reason}"),
+            ),
+            ApiError::InvalidSchedule { reason } => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid schedule: {reason}"),
+            ),
+            ApiError::InvalidPassword { max } => (
+                StatusCode::BAD_REQUEST,
+                format!("Password must be between 1 and {max} characters"),
+            ),
+            ApiError::PasswordHashFailed => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            ),
+            ApiError::InvalidQrOptions { reason } => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid QR code options: {reason}"),
+            ),
+            ApiError::QrRender(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            ),
             ApiError::TooManyCollisions => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Service temporarily unavailable".to_string(),
@@ -0,0 +1,58 @@
+use crate::watch::WatchedFile;
+use maxminddb::{Reader, geoip2};
+use std::{net::IpAddr, path::Path};
+use tracing::{info, warn};
+
+/// Resolves client IPs to countries from a local MaxMind format (`.mmdb`) database.
+/// Lookups never touch the network, and without a configured database every lookup misses.
+#[derive(Clone, Default)]
+pub struct GeoIp {
+    database: Option<WatchedFile<Reader<Vec<u8>>>>,
+}
+
+impl GeoIp {
+    /// Loads the database at `path`. A missing or unreadable file is logged and leaves
+    /// lookups disabled until the reload task picks up a valid file.
+    pub fn open(path: &Path) -> Self {
+        Self {
+            database: Some(WatchedFile::open(path, read_database)),
+        }
+    }
+
+    /// ISO 3166-1 alpha-2 code of the country the IP is registered in
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        self.database.as_ref()?.read(|reader| {
+            reader
+                .lookup::<geoip2::Country>(ip)
+                .ok()?
+                .country?
+                .iso_code
+                .map(str::to_string)
+        })?
+    }
+
+    /// Reloads the database whenever the file changes, so it can be swapped out without a
+    /// restart
+    pub fn start_reload_task(&self) {
+        if let Some(database) = &self.database {
+            database.start_reload_task();
+        }
+    }
+}
+
+fn read_database(path: &Path) -> Option<Reader<Vec<u8>>> {
+    match Reader::open_readfile(path) {
+        Ok(reader) => {
+            info!(
+                "Loaded GeoIP database {} from {}",
+                reader.metadata.database_type,
+                path.display()
+            );
+            Some(reader)
+        }
+        Err(e) => {
+            warn!("Failed to load GeoIP database {}: {}", path.display(), e);
+            None
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! How full the code space is, so saturation shows up well before links fail with
+//! too many collisions
+
+use crate::{
+    codes::{CodeGenerator, CodeStrategy, MAX_CODE_LENGTH},
+    db::queries::{stats, urls},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+/// How often the keyspace is checked against the warning threshold
+const MONITOR_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Serialize, Debug, ToSchema)]
+pub struct CodeLengthStats {
+    length: usize,
+    urls: i64,
+    /// Share of all codes of this length that are taken
+    fill_ratio: f64,
+    /// Codes of this length tried by this instance since it started
+    attempts: u64,
+    /// Tried codes that were already taken
+    collisions: u64,
+    collision_rate: f64,
+}
+
+#[derive(Clone, Serialize, Debug, ToSchema)]
+pub struct KeyspaceStats {
+    /// When these numbers were collected, they are refreshed every 5 minutes
+    checked_at: DateTime<Utc>,
+    /// `random`, `counter` or `hash`
+    strategy: String,
+    /// Length of newly generated codes
+    current_length: usize,
+    /// Estimated chance that a newly generated code is already taken. Counter based
+    /// codes never collide.
+    pub collision_probability: f64,
+    /// Whether the collision probability is above `KEYSPACE_WARN_THRESHOLD`
+    pub saturated: bool,
+    lengths: Vec<CodeLengthStats>,
+}
+
+/// Fill ratio and collisions for every code length in use
+pub async fn keyspace_stats(
+    pool: &PgPool,
+    codes: &CodeGenerator,
+    warn_threshold: f64,
+) -> Result<KeyspaceStats, sqlx::Error> {
+    let mut by_length: BTreeMap<usize, i64> = stats::get_code_lengths(pool)
+        .await?
+        .into_iter()
+        .map(|(length, urls)| (length.max(0) as usize, urls))
+        .collect();
+
+    // Counter codes only move on to a longer length once the shorter one is used up
+    let current_length = match codes.strategy() {
+        CodeStrategy::Counter => {
+            let next = urls::peek_code_number(pool).await?;
+            codes.counter_position(next.max(0) as u128).0
+        }
+        CodeStrategy::Random | CodeStrategy::Hash => codes.length(),
+    };
+    by_length.entry(current_length).or_default();
+    for length in 0..=MAX_CODE_LENGTH {
+        if codes.attempts(length).0 > 0 {
+            by_length.entry(length).or_default();
+        }
+    }
+
+    let fill_ratio = |length: usize, urls: i64| urls as f64 / codes.alphabet().space(length) as f64;
+    let lengths: Vec<CodeLengthStats> = by_length
+        .into_iter()
+        .map(|(length, urls)| {
+            let (attempts, collisions) = codes.attempts(length);
+            CodeLengthStats {
+                length,
+                urls,
+                fill_ratio: fill_ratio(length, urls),
+                attempts,
+                collisions,
+                collision_rate: if attempts > 0 {
+                    collisions as f64 / attempts as f64
+                } else {
+                    0.0
+                },
+            }
+        })
+        .collect();
+
+    // A random candidate collides about as often as its length's space is taken
+    let collision_probability = match codes.strategy() {
+        CodeStrategy::Counter => 0.0,
+        CodeStrategy::Random | CodeStrategy::Hash => lengths
+            .iter()
+            .find(|stats| stats.length == current_length)
+            .map_or(0.0, |stats| stats.fill_ratio),
+    };
+
+    Ok(KeyspaceStats {
+        checked_at: Utc::now(),
+        strategy: codes.strategy().to_string(),
+        current_length,
+        collision_probability,
+        saturated: collision_probability > warn_threshold,
+        lengths,
+    })
+}
+
+/// The latest code space usage collected by the monitor task, so health checks and stats
+/// requests don't query it on every call
+#[derive(Clone, Default)]
+pub struct KeyspaceMonitor {
+    latest: Arc<RwLock<Option<KeyspaceStats>>>,
+}
+
+impl KeyspaceMonitor {
+    /// Stats of the last successful check, `None` until the first one completed
+    pub fn latest(&self) -> Option<KeyspaceStats> {
+        self.latest
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Periodically collects the stats and logs a warning while the collision probability
+    /// is above the threshold. Also adopts code lengths grown by other replicas in the
+    /// meantime.
+    pub fn start_monitor_task(&self, pool: PgPool, codes: CodeGenerator, warn_threshold: f64) {
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = codes.sync_length(&pool).await {
+                    error!("Failed to read the stored code length: {}", e);
+                }
+
+                match keyspace_stats(&pool, &codes, warn_threshold).await {
+                    Ok(stats) => {
+                        if stats.saturated {
+                            warn!(
+                                "Code space is filling up, {:.2}% of new {} character codes collide. Raise CODE_LENGTH or switch CODE_STRATEGY to counter",
+                                stats.collision_probability * 100.0,
+                                stats.current_length
+                            );
+                        }
+                        *monitor
+                            .latest
+                            .write()
+                            .unwrap_or_else(PoisonError::into_inner) = Some(stats);
+                    }
+                    Err(e) => error!("Failed to check code space usage: {}", e),
+                }
+
+                tokio::time::sleep(MONITOR_INTERVAL).await;
+            }
+        });
+    }
+}
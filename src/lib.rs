@@ -1,6 +1,17 @@
 pub mod api;
+pub mod bots;
 pub mod cache;
+pub mod canonical;
+pub mod codes;
 pub mod config;
 pub mod db;
+pub mod domains;
 pub mod error;
+pub mod geoip;
+pub mod keyspace;
+pub mod links;
+pub mod qr;
 pub mod state;
+pub mod threats;
+pub mod utm;
+pub mod watch;
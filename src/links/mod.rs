@@ -0,0 +1,532 @@
+pub mod moderation;
+pub mod password;
+pub mod schedule;
+pub mod split;
+pub mod targeting;
+
+use axum::{
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use moderation::{DisableReason, LinkStatus};
+use schedule::{Availability, ScheduledDestination};
+use serde::{Deserialize, Serialize};
+use split::Variant;
+use std::str::FromStr;
+use targeting::{LinkRule, RuleKind, Visitor};
+use thiserror::Error;
+use url::{Url, form_urlencoded};
+use utoipa::ToSchema;
+
+/// Browsers and proxies may cache permanent redirects for this long
+const PERMANENT_REDIRECT_MAX_AGE_SECS: u64 = 86400;
+
+#[derive(Error, Debug)]
+#[error("Unsupported redirect type: {0}. Expected one of 301, 302, 307, 308")]
+pub struct InvalidRedirectType(pub String);
+
+/// HTTP status used when redirecting to a link's destination
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(try_from = "u16", into = "u16")]
+#[repr(i16)]
+pub enum RedirectType {
+    MovedPermanently = 301,
+    Found = 302,
+    TemporaryRedirect = 307,
+    PermanentRedirect = 308,
+}
+
+impl RedirectType {
+    pub fn status(self) -> StatusCode {
+        match self {
+            RedirectType::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+            RedirectType::Found => StatusCode::FOUND,
+            RedirectType::TemporaryRedirect => StatusCode::TEMPORARY_REDIRECT,
+            RedirectType::PermanentRedirect => StatusCode::PERMANENT_REDIRECT,
+        }
+    }
+
+    pub fn is_permanent(self) -> bool {
+        matches!(
+            self,
+            RedirectType::MovedPermanently | RedirectType::PermanentRedirect
+        )
+    }
+
+    /// Permanent redirects may be cached downstream for `max_age_secs`, temporary ones must
+    /// reach us every time so each click keeps being counted. `private` keeps permanent
+    /// redirects out of shared caches for destinations that depend on something caches
+    /// can't vary on, such as the client IP.
+    pub fn cache_control(self, private: bool, max_age_secs: u64) -> String {
+        match (self.is_permanent(), private) {
+            (false, _) => "no-store".to_string(),
+            (true, false) => format!("public, max-age={max_age_secs}"),
+            (true, true) => format!("private, max-age={max_age_secs}"),
+        }
+    }
+
+    pub fn to_response(self, location: &str, cache_control: String) -> Response {
+        (
+            self.status(),
+            [
+                (header::LOCATION, location.to_string()),
+                (header::CACHE_CONTROL, cache_control),
+            ],
+        )
+            .into_response()
+    }
+}
+
+impl TryFrom<u16> for RedirectType {
+    type Error = InvalidRedirectType;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            301 => Ok(RedirectType::MovedPermanently),
+            302 => Ok(RedirectType::Found),
+            307 => Ok(RedirectType::TemporaryRedirect),
+            308 => Ok(RedirectType::PermanentRedirect),
+            other => Err(InvalidRedirectType(other.to_string())),
+        }
+    }
+}
+
+impl From<RedirectType> for u16 {
+    fn from(value: RedirectType) -> Self {
+        value as u16
+    }
+}
+
+impl FromStr for RedirectType {
+    type Err = InvalidRedirectType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = s
+            .trim()
+            .parse::<u16>()
+            .map_err(|_| InvalidRedirectType(s.to_string()))?;
+        Self::try_from(value)
+    }
+}
+
+/// How a forwarded query parameter is handled when the destination already has the same key
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum QueryConflict {
+    /// Keep the destination's value and drop the incoming one
+    #[default]
+    Destination,
+    /// Replace the destination's values with the incoming ones
+    Incoming,
+    /// Keep both values
+    Both,
+}
+
+/// Everything needed to serve a redirect, stored in the cache as JSON
+#[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Link {
+    pub url: String,
+    /// Falls back to the service default when not set on the link
+    pub redirect_type: Option<RedirectType>,
+    /// Merge the query string of the short URL into the destination
+    pub forward_query: bool,
+    /// Append path segments after the code to the destination path
+    pub forward_path: bool,
+    pub query_conflict: QueryConflict,
+    /// Targeting rules overriding `url` for matching visitors, in evaluation order
+    #[sqlx(json)]
+    pub rules: Vec<LinkRule>,
+    /// Weighted A/B destinations used when no rule matches
+    #[sqlx(json)]
+    pub variants: Vec<Variant>,
+    /// The link 404s before this time
+    pub not_before: Option<DateTime<Utc>>,
+    /// The link is gone from this time on
+    pub not_after: Option<DateTime<Utc>>,
+    /// Destinations replacing `url` over time, ordered by start
+    #[sqlx(json)]
+    pub schedule: Vec<ScheduledDestination>,
+    /// Argon2 hash of the password visitors must enter before being redirected
+    pub password_hash: Option<String>,
+    /// The link stops working after its first redirect
+    pub single_use: bool,
+    /// When a single use link was followed
+    pub consumed_at: Option<DateTime<Utc>>,
+    /// Show a confirmation page with the destination instead of redirecting straight away
+    pub interstitial: bool,
+    /// When a destination matched the threat list. Disabled links serve a warning page.
+    pub threat_detected_at: Option<DateTime<Utc>>,
+    pub status: LinkStatus,
+    /// Set for disabled links
+    pub disabled_reason: Option<DisableReason>,
+}
+
+/// The destination a visit resolved to
+pub struct Target<'a> {
+    pub url: &'a str,
+    /// Set when the visitor was sent to one of the link's A/B variants
+    pub variant: Option<&'a Variant>,
+}
+
+impl Link {
+    pub fn availability(&self, now: DateTime<Utc>) -> Availability {
+        schedule::availability(self.not_before, self.not_after, now)
+    }
+
+    /// The default destination at `now`, taking scheduled switches into account
+    pub fn default_url(&self, now: DateTime<Utc>) -> &str {
+        schedule::current(&self.schedule, now).map_or(&self.url, |entry| &entry.url)
+    }
+
+    /// The next time the link's availability or default destination changes
+    pub fn next_boundary(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.schedule
+            .iter()
+            .map(|entry| entry.starts_at)
+            .chain(self.not_before)
+            .chain(self.not_after)
+            .filter(|boundary| *boundary > now)
+            .min()
+    }
+
+    /// Seconds until `now` plus `max_secs` or the next boundary, whichever comes first,
+    /// so nothing caches the link past a switch
+    pub fn ttl(&self, now: DateTime<Utc>, max_secs: u64) -> u64 {
+        self.next_boundary(now)
+            .and_then(|boundary| (boundary - now).to_std().ok())
+            .map_or(max_secs, |until| until.as_secs().min(max_secs))
+    }
+
+    /// How long browsers may cache a permanent redirect to this link. Protected and single
+    /// use links are never cached, or the browser would skip the password once the unlock
+    /// cookie expires or keep following a consumed link.
+    pub fn redirect_max_age(&self, now: DateTime<Utc>) -> u64 {
+        if self.password_hash.is_some() || self.single_use {
+            return 0;
+        }
+        self.ttl(now, PERMANENT_REDIRECT_MAX_AGE_SECS)
+    }
+
+    /// Picks the destination for the visitor: the first matching rule, else one of the
+    /// A/B variants, else the default URL in effect at `now`
+    pub fn target(&self, visitor: &Visitor, now: DateTime<Utc>) -> Target<'_> {
+        let language = visitor.negotiate_language(
+            self.rules
+                .iter()
+                .filter(|rule| rule.kind == RuleKind::Language)
+                .map(|rule| rule.value.as_str()),
+        );
+
+        let rule = self.rules.iter().find(|rule| match rule.kind {
+            RuleKind::Language => language == Some(rule.value.as_str()),
+            _ => rule.matches(visitor),
+        });
+        if let Some(rule) = rule {
+            return Target {
+                url: &rule.url,
+                variant: None,
+            };
+        }
+
+        match split::choose(&self.variants, visitor.sticky_variant.as_deref()) {
+            Some(variant) => Target {
+                url: &variant.url,
+                variant: Some(variant),
+            },
+            None => Target {
+                url: self.default_url(now),
+                variant: None,
+            },
+        }
+    }
+
+    /// Request headers the destination depends on, so shared caches must vary on them
+    pub fn vary_headers(&self) -> Vec<&'static str> {
+        let mut headers: Vec<&'static str> = self
+            .rules
+            .iter()
+            .filter_map(|rule| rule.kind.request_header())
+            .collect();
+        headers.sort_unstable();
+        headers.dedup();
+        headers
+    }
+
+    /// Whether the destination depends on something shared caches can't vary on, like the
+    /// client's country or their A/B variant
+    pub fn is_per_visitor(&self) -> bool {
+        !self.variants.is_empty()
+            || self.password_hash.is_some()
+            || self.single_use
+            || self.rules.iter().any(|rule| rule.kind == RuleKind::Country)
+    }
+
+    /// Builds the URL to redirect to from the resolved target, applying the link's
+    /// passthrough options to the raw (still percent-encoded) path suffix and query string
+    /// of the request
+    pub fn destination(
+        &self,
+        target: &str,
+        path_suffix: Option<&str>,
+        query: Option<&str>,
+    ) -> String {
+        let path_suffix = path_suffix.filter(|_| self.forward_path);
+        let query = query.filter(|q| self.forward_query && !q.is_empty());
+
+        if path_suffix.is_none() && query.is_none() {
+            return target.to_string();
+        }
+
+        let Ok(mut url) = Url::parse(target) else {
+            return target.to_string();
+        };
+
+        if let Some(suffix) = path_suffix {
+            append_path(&mut url, suffix);
+        }
+
+        if let Some(query) = query {
+            merge_query(&mut url, query, self.query_conflict);
+        }
+
+        url.into()
+    }
+}
+
+fn append_path(url: &mut Url, suffix: &str) {
+    // Dot segments would let the suffix climb out of the destination path, including
+    // percent-encoded ones the destination server decodes
+    let segments: Vec<&str> = suffix
+        .split('/')
+        .filter(|segment| !segment.is_empty() && !is_traversal(segment))
+        .collect();
+
+    if segments.is_empty() {
+        return;
+    }
+
+    let path = format!(
+        "{}/{}",
+        url.path().trim_end_matches('/'),
+        segments.join("/")
+    );
+    url.set_path(&path);
+}
+
+/// Whether a path segment is `.` or `..` once decoded, or hides a slash or backslash
+fn is_traversal(segment: &str) -> bool {
+    let decoded = segment
+        .to_ascii_lowercase()
+        .replace("%2e", ".")
+        .replace("%2f", "/")
+        .replace("%5c", "\\");
+    decoded == "." || decoded == ".." || decoded.contains(['/', '\\'])
+}
+
+/// Merges the raw pairs of both queries. Pairs are compared by their decoded key but kept
+/// as they were written, so parameters the merge doesn't touch keep their exact encoding.
+fn merge_query(url: &mut Url, query: &str, conflict: QueryConflict) {
+    let destination = url.query().unwrap_or_default().to_string();
+    let existing: Vec<&str> = raw_pairs(&destination).collect();
+    let incoming: Vec<&str> = raw_pairs(query).collect();
+    let has_key = |pairs: &[&str], pair: &str| {
+        let key = query_key(pair);
+        pairs.iter().any(|other| query_key(other) == key)
+    };
+
+    let pairs: Vec<&str> = match conflict {
+        QueryConflict::Destination => existing
+            .iter()
+            .copied()
+            .chain(
+                incoming
+                    .iter()
+                    .copied()
+                    .filter(|pair| !has_key(&existing, pair)),
+            )
+            .collect(),
+        QueryConflict::Incoming => existing
+            .iter()
+            .copied()
+            .filter(|pair| !has_key(&incoming, pair))
+            .chain(incoming.iter().copied())
+            .collect(),
+        QueryConflict::Both => existing.iter().chain(&incoming).copied().collect(),
+    };
+
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.set_query(Some(&pairs.join("&")));
+    }
+}
+
+/// The non-empty `key=value` pairs of a raw query string, still percent-encoded
+pub(crate) fn raw_pairs(query: &str) -> impl Iterator<Item = &str> {
+    query.split('&').filter(|pair| !pair.is_empty())
+}
+
+/// Decoded key of a raw query pair
+pub(crate) fn query_key(pair: &str) -> String {
+    form_urlencoded::parse(pair.as_bytes())
+        .next()
+        .map(|(key, _)| key.into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn appended(destination: &str, suffix: &str) -> String {
+        let mut url = Url::parse(destination).unwrap();
+        append_path(&mut url, suffix);
+        url.into()
+    }
+
+    fn merged(destination: &str, query: &str, conflict: QueryConflict) -> String {
+        let mut url = Url::parse(destination).unwrap();
+        merge_query(&mut url, query, conflict);
+        url.into()
+    }
+
+    #[test]
+    fn traversal_segments_are_detected() {
+        for segment in [
+            ".",
+            "..",
+            "%2e",
+            "%2e%2e",
+            "%2E%2E",
+            ".%2e",
+            "%2E.",
+            "a%2fb",
+            "a%2Fb",
+            "a%5cb",
+            "a%5Cb",
+            "..%2f",
+            "%2e%2e%5c",
+        ] {
+            assert!(is_traversal(segment), "{segment} should be rejected");
+        }
+    }
+
+    #[test]
+    fn ordinary_segments_are_allowed() {
+        for segment in [
+            "docs",
+            "v1.2",
+            "...",
+            "a.b",
+            "file.tar.gz",
+            "%20space",
+            "%2",
+        ] {
+            assert!(!is_traversal(segment), "{segment} should be allowed");
+        }
+    }
+
+    #[test]
+    fn traversal_never_leaves_the_destination_path() {
+        let destination = "https://example.com/base/";
+        for suffix in [
+            "../admin",
+            "%2e%2e/admin",
+            "%2E%2E/admin",
+            ".%2e/admin",
+            "./../admin",
+        ] {
+            assert_eq!(
+                appended(destination, suffix),
+                "https://example.com/base/admin",
+                "{suffix}"
+            );
+        }
+
+        // A segment hiding a separator is dropped whole
+        for suffix in [
+            "..%2fadmin",
+            "%2e%2e%2fadmin",
+            "%2e%2e%5cadmin",
+            "x%2F..%2F..%2Fetc",
+        ] {
+            assert_eq!(appended(destination, suffix), destination, "{suffix}");
+        }
+    }
+
+    #[test]
+    fn nested_paths_are_appended() {
+        assert_eq!(
+            appended("https://example.com/base", "a/b/c.html"),
+            "https://example.com/base/a/b/c.html"
+        );
+        assert_eq!(
+            appended("https://example.com/base/", "/a//b/"),
+            "https://example.com/base/a/b"
+        );
+        assert_eq!(
+            appended("https://example.com", "docs/v1.2"),
+            "https://example.com/docs/v1.2"
+        );
+        assert_eq!(
+            appended("https://example.com/base?x=1", "a%20b"),
+            "https://example.com/base/a%20b?x=1"
+        );
+    }
+
+    #[test]
+    fn destination_value_wins_on_conflict() {
+        assert_eq!(
+            merged(
+                "https://example.com/?a=1&b=2",
+                "a=9&c=3",
+                QueryConflict::Destination
+            ),
+            "https://example.com/?a=1&b=2&c=3"
+        );
+    }
+
+    #[test]
+    fn incoming_value_wins_on_conflict() {
+        assert_eq!(
+            merged(
+                "https://example.com/?a=1&b=2&a=4",
+                "a=9&c=3",
+                QueryConflict::Incoming
+            ),
+            "https://example.com/?b=2&a=9&c=3"
+        );
+    }
+
+    #[test]
+    fn both_values_are_kept_on_conflict() {
+        assert_eq!(
+            merged(
+                "https://example.com/?a=1&b=2",
+                "a=9&c=3",
+                QueryConflict::Both
+            ),
+            "https://example.com/?a=1&b=2&a=9&c=3"
+        );
+    }
+
+    #[test]
+    fn conflicting_keys_are_compared_decoded() {
+        assert_eq!(
+            merged(
+                "https://example.com/?a%20b=1",
+                "a+b=2&q=x%2By",
+                QueryConflict::Destination
+            ),
+            "https://example.com/?a%20b=1&q=x%2By"
+        );
+        assert_eq!(
+            merged("https://example.com/", "a=1", QueryConflict::Destination),
+            "https://example.com/?a=1"
+        );
+    }
+}
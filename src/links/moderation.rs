@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Moderation state of a link
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum LinkStatus {
+    #[default]
+    Active,
+    /// Taken down, visitors get a 410 or 451 page while the link's clicks are kept
+    Disabled,
+    /// Reported and waiting for review, visitors are shown the destination before continuing
+    Flagged,
+}
+
+/// Why a link was taken down, deciding between 410 Gone and 451 Unavailable For Legal Reasons
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum DisableReason {
+    #[default]
+    Abuse,
+    Legal,
+}
+
+/// What a visitor reported a link for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum ReportReason {
+    Phishing,
+    Malware,
+    Spam,
+    Illegal,
+    Other,
+}
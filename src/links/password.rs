@@ -0,0 +1,198 @@
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use axum::http::{HeaderMap, header};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// How long a visitor stays unlocked after entering the right password
+pub const UNLOCK_TTL_SECS: i64 = 3600;
+
+pub fn hash(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Checks the password against a stored hash. Deliberately slow, so callers on the async
+/// runtime should move it to a blocking thread.
+pub fn verify(password_hash: &str, password: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|parsed| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok()
+    })
+}
+
+fn cookie_name(code: &str) -> String {
+    format!("unlock_{code}")
+}
+
+/// The signature covers the password hash as well, so changing a link's password locks
+/// out everyone who unlocked it before
+fn mac(secret: &str, code: &str, password_hash: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(code.as_bytes());
+    mac.update(b"\0");
+    mac.update(password_hash.as_bytes());
+    mac.update(b"\0");
+    mac.update(expires.to_string().as_bytes());
+    mac
+}
+
+/// `Set-Cookie` value letting the visitor skip the password prompt for `UNLOCK_TTL_SECS`
+pub fn unlock_cookie(secret: &str, code: &str, password_hash: &str, now: DateTime<Utc>) -> String {
+    let expires = now.timestamp() + UNLOCK_TTL_SECS;
+    let signature = mac(secret, code, password_hash, expires)
+        .finalize()
+        .into_bytes();
+    format!(
+        "{}={expires}.{}; Path=/{code}; Max-Age={UNLOCK_TTL_SECS}; HttpOnly; SameSite=Lax",
+        cookie_name(code),
+        hex::encode(signature),
+    )
+}
+
+/// Whether the request carries an unexpired unlock cookie signed for this link and password
+pub fn is_unlocked(
+    headers: &HeaderMap,
+    secret: &str,
+    code: &str,
+    password_hash: &str,
+    now: DateTime<Utc>,
+) -> bool {
+    let name = cookie_name(code);
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .filter(|(key, _)| *key == name)
+        .any(|(_, value)| {
+            let Some((expires, signature)) = value.split_once('.') else {
+                return false;
+            };
+            let (Ok(expires), Some(signature)) =
+                (expires.parse::<i64>(), hex::decode(signature).ok())
+            else {
+                return false;
+            };
+            expires > now.timestamp()
+                && mac(secret, code, password_hash, expires)
+                    .verify_slice(&signature)
+                    .is_ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use chrono::{Duration, TimeZone};
+
+    const SECRET: &str = "secret";
+    const HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaGhhc2hoYXNoaGFzaA";
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 1, 1, 12, 0, 0).unwrap()
+    }
+
+    /// The `name=value` pair a browser sends back for a `Set-Cookie` value
+    fn cookie_pair(set_cookie: &str) -> &str {
+        set_cookie.split(';').next().unwrap()
+    }
+
+    fn headers(cookie: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_str(cookie).unwrap());
+        headers
+    }
+
+    #[test]
+    fn fresh_cookie_unlocks_the_link() {
+        let cookie = unlock_cookie(SECRET, "abc", HASH, now());
+        let headers = headers(&format!("other=1; {}", cookie_pair(&cookie)));
+        assert!(is_unlocked(&headers, SECRET, "abc", HASH, now()));
+        assert!(is_unlocked(
+            &headers,
+            SECRET,
+            "abc",
+            HASH,
+            now() + Duration::seconds(UNLOCK_TTL_SECS - 1)
+        ));
+    }
+
+    #[test]
+    fn expired_cookie_is_rejected() {
+        let cookie = unlock_cookie(SECRET, "abc", HASH, now());
+        let headers = headers(cookie_pair(&cookie));
+        let later = now() + Duration::seconds(UNLOCK_TTL_SECS);
+        assert!(!is_unlocked(&headers, SECRET, "abc", HASH, later));
+    }
+
+    #[test]
+    fn tampered_cookie_is_rejected() {
+        let cookie = unlock_cookie(SECRET, "abc", HASH, now());
+        let (name, value) = cookie_pair(&cookie).split_once('=').unwrap();
+        let (expires, signature) = value.split_once('.').unwrap();
+
+        let mut flipped = signature.to_string();
+        let last = if flipped.ends_with('0') { "1" } else { "0" };
+        flipped.replace_range(flipped.len() - 1.., last);
+        let extended = expires.parse::<i64>().unwrap() + 86400;
+
+        for forged in [
+            format!("{name}={expires}.{flipped}"),
+            format!("{name}={extended}.{signature}"),
+            format!("{name}={expires}.{}", &signature[..signature.len() - 2]),
+            format!("{name}={expires}"),
+            format!("{name}=.{signature}"),
+        ] {
+            assert!(
+                !is_unlocked(&headers(&forged), SECRET, "abc", HASH, now()),
+                "{forged}"
+            );
+        }
+        assert!(!is_unlocked(
+            &headers(cookie_pair(&cookie)),
+            "other secret",
+            "abc",
+            HASH,
+            now()
+        ));
+    }
+
+    #[test]
+    fn cookie_for_another_code_is_rejected() {
+        let cookie = unlock_cookie(SECRET, "abc", HASH, now());
+        let pair = cookie_pair(&cookie);
+        assert!(!is_unlocked(&headers(pair), SECRET, "xyz", HASH, now()));
+
+        // Renaming the cookie doesn't carry the signature over either
+        let renamed = pair.replacen("unlock_abc", "unlock_xyz", 1);
+        assert!(!is_unlocked(&headers(&renamed), SECRET, "xyz", HASH, now()));
+    }
+
+    #[test]
+    fn cookie_from_before_a_password_change_is_rejected() {
+        let old_hash = hash("old password").unwrap();
+        let new_hash = hash("new password").unwrap();
+        let cookie = unlock_cookie(SECRET, "abc", &old_hash, now());
+        let headers = headers(cookie_pair(&cookie));
+        assert!(is_unlocked(&headers, SECRET, "abc", &old_hash, now()));
+        assert!(!is_unlocked(&headers, SECRET, "abc", &new_hash, now()));
+    }
+
+    #[test]
+    fn passwords_are_verified_against_their_hash() {
+        let hashed = hash("hunter2").unwrap();
+        assert!(verify(&hashed, "hunter2"));
+        assert!(!verify(&hashed, "hunter3"));
+        assert!(!verify("not a hash", "hunter2"));
+    }
+}
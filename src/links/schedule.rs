@@ -0,0 +1,42 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Replaces the link's default destination from `starts_at` until the next entry starts
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScheduledDestination {
+    #[schema(example = "2026-06-01T18:00:00Z")]
+    pub starts_at: DateTime<Utc>,
+    #[schema(example = "https://example.com/event-recap")]
+    pub url: String,
+}
+
+/// Where `now` falls relative to a link's activation window
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Availability {
+    NotYetActive,
+    Active,
+    Ended,
+}
+
+pub fn availability(
+    not_before: Option<DateTime<Utc>>,
+    not_after: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Availability {
+    if not_before.is_some_and(|start| now < start) {
+        Availability::NotYetActive
+    } else if not_after.is_some_and(|end| now >= end) {
+        Availability::Ended
+    } else {
+        Availability::Active
+    }
+}
+
+/// The entry in effect at `now`. Entries are kept sorted by `starts_at`.
+pub fn current(
+    schedule: &[ScheduledDestination],
+    now: DateTime<Utc>,
+) -> Option<&ScheduledDestination> {
+    schedule.iter().rev().find(|entry| entry.starts_at <= now)
+}
@@ -0,0 +1,152 @@
+use axum::http::{HeaderMap, header};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Visitors keep seeing the variant they were first served for this long
+pub const STICKY_MAX_AGE_SECS: u64 = 30 * 86400;
+
+const LABEL_MAX_LEN: usize = 32;
+
+/// One destination of an A/B split, served to a share of visitors proportional to its weight
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Variant {
+    /// Identifies the variant in stats and in the sticky cookie
+    #[schema(example = "b")]
+    pub label: String,
+    #[schema(example = "https://example.com/landing-b")]
+    pub url: String,
+    #[schema(example = 30, minimum = 1)]
+    pub weight: i32,
+}
+
+impl Variant {
+    /// Labels end up in a cookie value, so they're restricted to a safe character set
+    pub fn has_valid_label(&self) -> bool {
+        (1..=LABEL_MAX_LEN).contains(&self.label.len())
+            && self
+                .label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+}
+
+/// Returns the variant the visitor was served before if it is still served, otherwise draws
+/// one at random by weight
+pub fn choose<'a>(variants: &'a [Variant], sticky: Option<&str>) -> Option<&'a Variant> {
+    let served = variants.iter().filter(|variant| variant.weight > 0);
+    if let Some(variant) = sticky.and_then(|label| served.clone().find(|v| v.label == label)) {
+        return Some(variant);
+    }
+
+    let total: i64 = served.map(|v| i64::from(v.weight)).sum();
+    if total == 0 {
+        return None;
+    }
+
+    draw(variants, rand::rng().random_range(0..total))
+}
+
+/// The variant the roll lands on when every variant covers a range of rolls as wide as
+/// its weight, rolls ranging over `0..` the total weight
+fn draw(variants: &[Variant], mut roll: i64) -> Option<&Variant> {
+    variants.iter().find(|variant| {
+        let weight = i64::from(variant.weight.max(0));
+        if roll < weight {
+            return true;
+        }
+        roll -= weight;
+        false
+    })
+}
+
+fn cookie_name(code: &str) -> String {
+    format!("variant_{code}")
+}
+
+/// Reads the label of the variant previously served for `code` from the request cookies
+pub fn sticky_label(headers: &HeaderMap, code: &str) -> Option<String> {
+    let name = cookie_name(code);
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+/// `Set-Cookie` value pinning the visitor to `label`, scoped to the short link's path
+pub fn sticky_cookie(code: &str, label: &str) -> String {
+    format!(
+        "{}={label}; Path=/{code}; Max-Age={STICKY_MAX_AGE_SECS}; HttpOnly; SameSite=Lax",
+        cookie_name(code)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weighted(weights: &[(&str, i32)]) -> Vec<Variant> {
+        weights
+            .iter()
+            .map(|&(label, weight)| Variant {
+                label: label.to_string(),
+                url: format!("https://example.com/{label}"),
+                weight,
+            })
+            .collect()
+    }
+
+    fn label(variant: Option<&Variant>) -> Option<&str> {
+        variant.map(|variant| variant.label.as_str())
+    }
+
+    #[test]
+    fn sticky_label_is_kept_while_the_variant_exists() {
+        let variants = weighted(&[("a", 1), ("b", 99)]);
+        for _ in 0..100 {
+            assert_eq!(label(choose(&variants, Some("a"))), Some("a"));
+        }
+    }
+
+    #[test]
+    fn removed_or_unserved_sticky_variants_are_redrawn() {
+        let variants = weighted(&[("a", 0), ("b", 1)]);
+        assert_eq!(label(choose(&variants, Some("gone"))), Some("b"));
+        assert_eq!(label(choose(&variants, Some("a"))), Some("b"));
+    }
+
+    #[test]
+    fn zero_weight_variants_are_never_picked() {
+        let variants = weighted(&[("a", 0), ("b", 3), ("c", 0), ("d", 1), ("e", -2)]);
+        for roll in 0..4 {
+            assert!(matches!(label(draw(&variants, roll)), Some("b" | "d")));
+        }
+        for _ in 0..1000 {
+            assert!(matches!(label(choose(&variants, None)), Some("b" | "d")));
+        }
+        assert!(choose(&weighted(&[("a", 0)]), None).is_none());
+        assert!(choose(&[], Some("a")).is_none());
+    }
+
+    #[test]
+    fn rolls_are_shared_out_by_weight() {
+        let variants = weighted(&[("a", 70), ("b", 0), ("c", 20), ("d", 10)]);
+        let mut counts = std::collections::HashMap::new();
+        for roll in 0..100 {
+            *counts
+                .entry(label(draw(&variants, roll)).unwrap())
+                .or_insert(0) += 1;
+        }
+        assert_eq!(counts.get("a"), Some(&70));
+        assert_eq!(counts.get("b"), None);
+        assert_eq!(counts.get("c"), Some(&20));
+        assert_eq!(counts.get("d"), Some(&10));
+        assert_eq!(label(draw(&variants, 69)), Some("a"));
+        assert_eq!(label(draw(&variants, 70)), Some("c"));
+        assert_eq!(label(draw(&variants, 100)), None);
+    }
+}
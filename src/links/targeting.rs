@@ -0,0 +1,329 @@
+use axum::http::{HeaderMap, header};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use utoipa::ToSchema;
+use woothee::parser::Parser;
+
+/// What a targeting rule matches the visitor on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum RuleKind {
+    /// Operating system or device class parsed from the `User-Agent` header
+    Platform,
+    /// Country the client IP resolves to in the GeoIP database
+    Country,
+    /// Language negotiated from the `Accept-Language` header
+    Language,
+}
+
+impl RuleKind {
+    /// Request header the rule reads, which responses must list in `Vary`
+    pub fn request_header(self) -> Option<&'static str> {
+        match self {
+            RuleKind::Platform => Some("User-Agent"),
+            RuleKind::Country => None,
+            RuleKind::Language => Some("Accept-Language"),
+        }
+    }
+}
+
+/// Caps how many `Accept-Language` entries are considered
+const ACCEPT_LANGUAGE_LIMIT: usize = 16;
+
+/// Sends visitors matching `kind`/`value` to `url` instead of the link's default destination.
+/// Rules are evaluated in order and the first match wins.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct LinkRule {
+    pub kind: RuleKind,
+    /// For `platform`: ios, android, windows, macos, linux, mobile or desktop.
+    /// For `country`: an ISO 3166-1 alpha-2 code such as `DE`.
+    /// For `language`: a language tag such as `de`, which also matches `de-AT`
+    #[schema(example = "ios")]
+    pub value: String,
+    #[schema(example = "https://apps.apple.com/app/id000000000")]
+    pub url: String,
+}
+
+impl LinkRule {
+    /// Checks the rule's value is something it can ever match
+    pub fn is_valid(&self) -> bool {
+        match self.kind {
+            RuleKind::Platform => self.value.parse::<Platform>().is_ok(),
+            RuleKind::Country => {
+                self.value.len() == 2 && self.value.chars().all(|c| c.is_ascii_alphabetic())
+            }
+            RuleKind::Language => is_language_tag(&self.value),
+        }
+    }
+
+    pub fn matches(&self, visitor: &Visitor) -> bool {
+        match self.kind {
+            RuleKind::Platform => self
+                .value
+                .parse::<Platform>()
+                .is_ok_and(|platform| visitor.platforms.contains(&platform)),
+            RuleKind::Country => visitor
+                .country
+                .as_deref()
+                .is_some_and(|country| country.eq_ignore_ascii_case(&self.value)),
+            RuleKind::Language => visitor
+                .languages
+                .iter()
+                .any(|language| language_matches(&self.value, language)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    Ios,
+    Android,
+    Windows,
+    Macos,
+    Linux,
+    Mobile,
+    Desktop,
+}
+
+impl FromStr for Platform {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ios" => Ok(Platform::Ios),
+            "android" => Ok(Platform::Android),
+            "windows" => Ok(Platform::Windows),
+            "macos" => Ok(Platform::Macos),
+            "linux" => Ok(Platform::Linux),
+            "mobile" => Ok(Platform::Mobile),
+            "desktop" => Ok(Platform::Desktop),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Visitor attributes targeting rules are matched against
+#[derive(Debug, Default)]
+pub struct Visitor {
+    /// Every platform the visitor belongs to, e.g. an iPhone is both `ios` and `mobile`
+    pub platforms: Vec<Platform>,
+    /// ISO 3166-1 alpha-2 country code, when the client IP could be resolved
+    pub country: Option<String>,
+    /// Lowercased language tags from `Accept-Language`, most preferred first
+    pub languages: Vec<String>,
+    /// Label of the A/B variant the visitor was served before, from their sticky cookie
+    pub sticky_variant: Option<String>,
+}
+
+impl Visitor {
+    pub fn new(headers: &HeaderMap, country: Option<String>) -> Self {
+        let header = |name| headers.get(name).and_then(|h| h.to_str().ok());
+        Self {
+            platforms: header(header::USER_AGENT)
+                .map(platforms)
+                .unwrap_or_default(),
+            country,
+            languages: header(header::ACCEPT_LANGUAGE)
+                .map(accept_languages)
+                .unwrap_or_default(),
+            sticky_variant: None,
+        }
+    }
+
+    /// Picks the language rule value that best fits the visitor's preferences. Unlike other
+    /// rules, language rules compete on the visitor's quality values rather than rule order.
+    pub fn negotiate_language<'a>(
+        &self,
+        available: impl Iterator<Item = &'a str> + Clone,
+    ) -> Option<&'a str> {
+        self.languages.iter().find_map(|language| {
+            available
+                .clone()
+                .find(|tag| language_matches(tag, language))
+        })
+    }
+}
+
+/// Parses an `Accept-Language` header into tags ordered by quality value, dropping
+/// wildcards and anything explicitly refused with `q=0`
+fn accept_languages(header: &str) -> Vec<String> {
+    let mut weighted: Vec<(String, f32)> = header
+        .split(',')
+        .take(ACCEPT_LANGUAGE_LIMIT)
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .next()
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+
+            (quality > 0.0 && is_language_tag(tag)).then(|| (tag.to_ascii_lowercase(), quality))
+        })
+        .collect();
+
+    // Stable, so equally weighted languages keep the order the client sent them in
+    weighted.sort_by(|a, b| b.1.total_cmp(&a.1));
+    weighted.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// A rule for `de` covers the `de-AT` and `de-CH` variants, but `de-AT` doesn't cover `de`
+fn language_matches(rule: &str, language: &str) -> bool {
+    language.len() >= rule.len()
+        && language[..rule.len()].eq_ignore_ascii_case(rule)
+        && matches!(language.as_bytes().get(rule.len()), None | Some(b'-'))
+}
+
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    subtags.next().is_some_and(|primary| {
+        (1..=8).contains(&primary.len()) && primary.chars().all(|c| c.is_ascii_alphabetic())
+    }) && subtags.all(|subtag| {
+        (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+fn platforms(user_agent: &str) -> Vec<Platform> {
+    let Some(result) = Parser::new().parse(user_agent) else {
+        return Vec::new();
+    };
+
+    let mut platforms = Vec::new();
+
+    match result.os {
+        "iPhone" | "iPad" | "iPod" => platforms.push(Platform::Ios),
+        "Android" => platforms.push(Platform::Android),
+        "Mac OSX" => platforms.push(Platform::Macos),
+        "Linux" => platforms.push(Platform::Linux),
+        os if os.starts_with("Windows") && !os.starts_with("Windows Phone") => {
+            platforms.push(Platform::Windows)
+        }
+        _ => {}
+    }
+
+    match result.category {
+        "smartphone" | "mobilephone" => platforms.push(Platform::Mobile),
+        "pc" => platforms.push(Platform::Desktop),
+        _ => {}
+    }
+
+    platforms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1";
+    const ANDROID: &str = "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Mobile Safari/537.36";
+    const WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+
+    fn rule(kind: RuleKind, value: &str) -> LinkRule {
+        LinkRule {
+            kind,
+            value: value.to_string(),
+            url: "https://example.com/".to_string(),
+        }
+    }
+
+    fn visitor(user_agent: &str, accept_language: &str, country: Option<&str>) -> Visitor {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_str(user_agent).unwrap(),
+        );
+        headers.insert(
+            header::ACCEPT_LANGUAGE,
+            HeaderValue::from_str(accept_language).unwrap(),
+        );
+        Visitor::new(&headers, country.map(str::to_string))
+    }
+
+    #[test]
+    fn languages_are_ordered_by_quality() {
+        assert_eq!(
+            accept_languages("fr;q=0.5, de-AT, en;q=0.8, it;q=0.5"),
+            ["de-at", "en", "fr", "it"]
+        );
+    }
+
+    #[test]
+    fn refused_and_wildcard_languages_are_dropped() {
+        assert_eq!(accept_languages("de;q=0, en, *;q=0.1"), ["en"]);
+        assert_eq!(accept_languages("*"), Vec::<String>::new());
+        assert_eq!(accept_languages("fr;q=0.0, es;q=abc"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn language_rules_match_regional_variants_only_downwards() {
+        assert!(language_matches("de", "de-at"));
+        assert!(language_matches("de", "de"));
+        assert!(language_matches("DE-at", "de-AT"));
+        assert!(!language_matches("de-at", "de"));
+        assert!(!language_matches("de", "deu"));
+        assert!(!language_matches("de-at", "de-ch"));
+    }
+
+    #[test]
+    fn negotiation_follows_the_visitors_preference() {
+        let preferring = visitor(WINDOWS, "fr, de-AT;q=0.9, en;q=0.5", None);
+        let available = ["en", "de"];
+        assert_eq!(
+            preferring.negotiate_language(available.iter().copied()),
+            Some("de")
+        );
+
+        let refusing = visitor(WINDOWS, "de;q=0, en;q=0.1", None);
+        assert_eq!(
+            refusing.negotiate_language(available.iter().copied()),
+            Some("en")
+        );
+
+        let regional_rule = visitor(WINDOWS, "de", None);
+        assert_eq!(
+            regional_rule.negotiate_language(["de-at"].into_iter()),
+            None
+        );
+    }
+
+    #[test]
+    fn platform_rules_match_the_user_agent() {
+        let iphone = visitor(IPHONE, "en", None);
+        assert!(rule(RuleKind::Platform, "ios").matches(&iphone));
+        assert!(rule(RuleKind::Platform, "mobile").matches(&iphone));
+        assert!(!rule(RuleKind::Platform, "android").matches(&iphone));
+        assert!(!rule(RuleKind::Platform, "desktop").matches(&iphone));
+
+        let android = visitor(ANDROID, "en", None);
+        assert!(rule(RuleKind::Platform, "Android").matches(&android));
+        assert!(!rule(RuleKind::Platform, "ios").matches(&android));
+
+        let windows = visitor(WINDOWS, "en", None);
+        assert!(rule(RuleKind::Platform, "windows").matches(&windows));
+        assert!(rule(RuleKind::Platform, "desktop").matches(&windows));
+        assert!(!rule(RuleKind::Platform, "mobile").matches(&windows));
+    }
+
+    #[test]
+    fn country_rules_match_the_resolved_country() {
+        let german = visitor(WINDOWS, "en", Some("DE"));
+        assert!(rule(RuleKind::Country, "de").matches(&german));
+        assert!(rule(RuleKind::Country, "DE").matches(&german));
+        assert!(!rule(RuleKind::Country, "AT").matches(&german));
+
+        let unknown = visitor(WINDOWS, "en", None);
+        assert!(!rule(RuleKind::Country, "DE").matches(&unknown));
+    }
+
+    #[test]
+    fn rule_values_are_validated() {
+        assert!(rule(RuleKind::Platform, "macos").is_valid());
+        assert!(!rule(RuleKind::Platform, "beos").is_valid());
+        assert!(rule(RuleKind::Country, "at").is_valid());
+        assert!(!rule(RuleKind::Country, "AUT").is_valid());
+        assert!(rule(RuleKind::Language, "de-AT").is_valid());
+        assert!(!rule(RuleKind::Language, "*").is_valid());
+    }
+}
@@ -1,9 +1,21 @@
-use turbo_guacamole::{api, cache, config, db, state::AppState};
+use turbo_guacamole::{
+    api,
+    cache::{self, code_pool::CodePool},
+    codes::CodeGenerator,
+    config,
+    db::{self, queries::moderation},
+    domains::DomainPolicy,
+    geoip::GeoIp,
+    keyspace::KeyspaceMonitor,
+    state::AppState,
+    threats::ThreatList,
+};
 
 use std::{net::SocketAddr, sync::Arc};
 use tokio::signal;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use uuid::Uuid;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -12,7 +24,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let config = config::Config::from_env();
 
     info!(
-        "Server configuration loaded: service_host={}, service_port={}, database_url={}, stale_url_days={}, cache_url={}, redirect_rate_limit={:?}, shorten_rate_limit={:?}",
+        "Server configuration loaded: service_host={}, service_port={}, database_url={}, stale_url_days={}, click_retention_days={}, cache_url={}, client_ip_header={:?}, geoip_db_path={:?}, domain_blocklist_path={:?}, domain_allowlist_path={:?}, threat_list_path={:?}, public_base_url={:?}, default_redirect_type={}, code_strategy={}, code_length={}, code_alphabet={}, code_secret_set={}, code_pool_size={}, keyspace_warn_threshold={}, strip_tracking_params={}, url_fragment_policy={}, admin_token_set={}, report_hash_secret_set={}, report_flag_threshold={}, redirect_rate_limit={:?}, shorten_rate_limit={:?}",
         config.service_host,
         config.service_port,
         if config.database_url.len() > 15 {
@@ -21,29 +33,124 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             config.database_url.clone()
         },
         config.stale_urls_days,
+        config.click_retention_days,
         if config.cache_url.len() > 15 {
             format!("{}...", &config.cache_url[..15])
         } else {
             config.cache_url.clone()
         },
+        config.client_ip_header,
+        config.geoip_db_path,
+        config.domain_blocklist_path,
+        config.domain_allowlist_path,
+        config.threat_list_path,
+        config.public_base_url,
+        u16::from(config.default_redirect_type),
+        config.code_strategy,
+        config.code_length,
+        config.code_alphabet,
+        config.code_secret.is_some(),
+        config.code_pool_size,
+        config.keyspace_warn_threshold,
+        config.strip_tracking_params,
+        config.url_fragment_policy,
+        config.admin_token.is_some(),
+        config.report_hash_secret.is_some(),
+        config.report_flag_threshold,
         config.redirect_rate_limit_config,
         config.shorten_rate_limit_config,
     );
 
+    let codes = CodeGenerator::new(
+        config.code_strategy,
+        config.code_alphabet.clone(),
+        config.code_length,
+        config.code_secret.as_deref(),
+    )?;
+
     // set up postgres connection pool
     let pg_pool = db::setup_database(&config.database_url).await?;
     info!("Postgres connection established");
 
+    // continue at the length codes grew to before a restart
+    codes.sync_length(&pg_pool).await?;
+
+    // reporters must hash the same across restarts and replicas to be counted once
+    let report_hash_secret = match &config.report_hash_secret {
+        Some(secret) => secret.clone(),
+        None => {
+            warn!(
+                "REPORT_HASH_SECRET is not set, reporter hashes are salted with a value stored in the database and can be reversed by anyone who can read it"
+            );
+            moderation::report_hash_salt(&pg_pool, &Uuid::new_v4().simple().to_string()).await?
+        }
+    };
+
     // start stale URL cleanup task
     db::start_cleanup_task(pg_pool.clone(), config.stale_urls_days);
 
+    // start click partition maintenance task
+    db::start_partition_task(pg_pool.clone(), config.click_retention_days);
+
+    // start click rollup task
+    db::start_rollup_task(pg_pool.clone(), config.click_retention_days);
+
     // set up redis connection pool
     let redis_pool = cache::setup_cache(&config.cache_url).await?;
     info!("Redis connection established");
 
+    // start unique visitor persistence task
+    cache::visitors::start_persist_task(redis_pool.clone(), pg_pool.clone());
+
+    // keep a pool of reserved codes topped up
+    let code_pool = CodePool::new(config.code_pool_size, &codes);
+    code_pool.start_refill_task(redis_pool.clone(), pg_pool.clone());
+
+    // warn when new codes start colliding often, and pick up lengths grown by other replicas
+    let keyspace = KeyspaceMonitor::default();
+    keyspace.start_monitor_task(
+        pg_pool.clone(),
+        codes.clone(),
+        config.keyspace_warn_threshold,
+    );
+
+    // load the GeoIP database and watch it for updates
+    let geoip = match &config.geoip_db_path {
+        Some(path) => {
+            let geoip = GeoIp::open(path);
+            geoip.start_reload_task();
+            geoip
+        }
+        None => GeoIp::default(),
+    };
+
+    // load the destination domain lists and watch them for updates
+    let domains = DomainPolicy::open(
+        config.domain_blocklist_path.as_deref(),
+        config.domain_allowlist_path.as_deref(),
+    );
+    domains.start_reload_task();
+
+    // load the threat list and disable existing links it matches
+    let threats = match &config.threat_list_path {
+        Some(path) => {
+            let threats = ThreatList::open(path);
+            threats.start_scan_task(pg_pool.clone(), redis_pool.clone());
+            threats
+        }
+        None => ThreatList::default(),
+    };
+
     let app_state = Arc::new(AppState {
         pg_pool,
         redis_pool,
+        geoip,
+        domains,
+        threats,
+        codes,
+        code_pool,
+        keyspace,
+        report_hash_secret,
         config: config.clone(),
     });
 
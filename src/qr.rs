@@ -0,0 +1,180 @@
+use image::{ImageEncoder, Rgb, RgbImage, codecs::png::PngEncoder};
+use qrcode::{Color, EcLevel, QrCode};
+use serde::Deserialize;
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+pub const MIN_SIZE: u32 = 64;
+pub const MAX_SIZE: u32 = 2048;
+pub const MAX_MARGIN: u32 = 16;
+
+#[derive(Error, Debug)]
+pub enum QrError {
+    #[error("failed to encode QR code: {0}")]
+    Encode(#[from] qrcode::types::QrError),
+
+    #[error("failed to encode PNG: {0}")]
+    Png(#[from] image::ImageError),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+impl QrFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            QrFormat::Png => "image/png",
+            QrFormat::Svg => "image/svg+xml",
+        }
+    }
+}
+
+/// Error correction level, higher levels survive more damage at the cost of denser codes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+pub enum ErrorCorrection {
+    #[serde(alias = "l")]
+    L,
+    #[default]
+    #[serde(alias = "m")]
+    M,
+    #[serde(alias = "q")]
+    Q,
+    #[serde(alias = "h")]
+    H,
+}
+
+impl From<ErrorCorrection> for EcLevel {
+    fn from(value: ErrorCorrection) -> Self {
+        match value {
+            ErrorCorrection::L => EcLevel::L,
+            ErrorCorrection::M => EcLevel::M,
+            ErrorCorrection::Q => EcLevel::Q,
+            ErrorCorrection::H => EcLevel::H,
+        }
+    }
+}
+
+/// An RGB color written as six hex digits, with or without a leading `#`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HexColor([u8; 3]);
+
+impl HexColor {
+    pub const BLACK: HexColor = HexColor([0, 0, 0]);
+    pub const WHITE: HexColor = HexColor([255, 255, 255]);
+}
+
+impl FromStr for HexColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("invalid color {s:?}, expected six hex digits"));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).expect("checked hex");
+        Ok(HexColor([channel(0), channel(2), channel(4)]))
+    }
+}
+
+impl fmt::Display for HexColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "#{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct QrOptions {
+    pub format: QrFormat,
+    /// Requested width and height in pixels. PNGs are rounded down to a whole number of
+    /// pixels per module, never below one.
+    pub size: u32,
+    /// Quiet zone around the code, in modules
+    pub margin: u32,
+    pub error_correction: ErrorCorrection,
+    pub foreground: HexColor,
+    pub background: HexColor,
+}
+
+impl QrOptions {
+    /// Identifies the rendered image, used as part of its cache key
+    pub fn cache_key(&self) -> String {
+        format!(
+            "{:?}:{}:{}:{:?}:{}:{}",
+            self.format,
+            self.size,
+            self.margin,
+            self.error_correction,
+            self.foreground,
+            self.background
+        )
+    }
+}
+
+/// Renders `content` as a QR code image in the requested format
+pub fn render(content: &str, options: &QrOptions) -> Result<Vec<u8>, QrError> {
+    let code = QrCode::with_error_correction_level(content, options.error_correction.into())?;
+    let width = code.width() as u32;
+    let dark: Vec<bool> = code
+        .to_colors()
+        .into_iter()
+        .map(|c| c == Color::Dark)
+        .collect();
+
+    match options.format {
+        QrFormat::Png => render_png(&dark, width, options),
+        QrFormat::Svg => Ok(render_svg(&dark, width, options).into_bytes()),
+    }
+}
+
+fn render_png(dark: &[bool], width: u32, options: &QrOptions) -> Result<Vec<u8>, QrError> {
+    let total = width + 2 * options.margin;
+    let scale = (options.size / total).max(1);
+    let pixels = total * scale;
+
+    let image = RgbImage::from_fn(pixels, pixels, |x, y| {
+        let module_x = (x / scale).checked_sub(options.margin);
+        let module_y = (y / scale).checked_sub(options.margin);
+        let is_dark = match (module_x, module_y) {
+            (Some(mx), Some(my)) if mx < width && my < width => dark[(my * width + mx) as usize],
+            _ => false,
+        };
+        Rgb(if is_dark {
+            options.foreground.0
+        } else {
+            options.background.0
+        })
+    });
+
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(
+        image.as_raw(),
+        pixels,
+        pixels,
+        image::ExtendedColorType::Rgb8,
+    )?;
+    Ok(png)
+}
+
+fn render_svg(dark: &[bool], width: u32, options: &QrOptions) -> String {
+    let total = width + 2 * options.margin;
+    let mut path = String::new();
+    for (i, _) in dark.iter().enumerate().filter(|(_, is_dark)| **is_dark) {
+        let x = i as u32 % width + options.margin;
+        let y = i as u32 / width + options.margin;
+        path.push_str(&format!("M{x} {y}h1v1h-1z"));
+    }
+
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="0 0 {total} {total}" shape-rendering="crispEdges"><rect width="{total}" height="{total}" fill="{bg}"/><path d="{path}" fill="{fg}"/></svg>"#,
+        size = options.size,
+        bg = options.background,
+        fg = options.foreground,
+    )
+}
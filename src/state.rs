@@ -1,8 +1,24 @@
-use crate::{cache::RedisPool, config::Config};
+use crate::{
+    cache::{RedisPool, code_pool::CodePool},
+    codes::CodeGenerator,
+    config::Config,
+    domains::DomainPolicy,
+    geoip::GeoIp,
+    keyspace::KeyspaceMonitor,
+    threats::ThreatList,
+};
 use sqlx::postgres::PgPool;
 
 pub struct AppState {
     pub pg_pool: PgPool,
     pub redis_pool: RedisPool,
+    pub geoip: GeoIp,
+    pub domains: DomainPolicy,
+    pub threats: ThreatList,
+    pub codes: CodeGenerator,
+    pub code_pool: CodePool,
+    pub keyspace: KeyspaceMonitor,
+    /// `REPORT_HASH_SECRET`, or the salt stored in Postgres while it is unset
+    pub report_hash_secret: String,
     pub config: Config,
 }
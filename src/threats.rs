@@ -0,0 +1,506 @@
+use crate::{
+    cache::{RedisPool, remove_from_cache},
+    db::queries::urls,
+    watch::WatchedFile,
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+    time::Duration,
+};
+use tracing::{error, info, warn};
+use url::{Host, Url};
+
+/// Links checked per query while scanning existing links
+const SCAN_BATCH_SIZE: i64 = 500;
+
+/// How often existing links are scanned when the list file doesn't change
+const SCAN_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How closely a URL matched the list
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThreatMatch {
+    /// Only a hash prefix matched, which may be a collision with a harmless URL
+    Prefix,
+    /// The full SHA-256 hash of an expression is on the list
+    Full,
+}
+
+/// SHA-256 hash prefixes of unsafe URL expressions, grouped by prefix length
+#[derive(Debug, Default)]
+struct HashPrefixes {
+    by_len: BTreeMap<usize, HashSet<Vec<u8>>>,
+}
+
+impl HashPrefixes {
+    fn insert(&mut self, prefix: &[u8]) -> bool {
+        if !(4..=32).contains(&prefix.len()) {
+            return false;
+        }
+        self.by_len
+            .entry(prefix.len())
+            .or_default()
+            .insert(prefix.to_vec());
+        true
+    }
+
+    fn find(&self, hash: &[u8]) -> Option<ThreatMatch> {
+        self.by_len
+            .iter()
+            .filter(|(len, prefixes)| prefixes.contains(&hash[..**len]))
+            .map(|(len, _)| {
+                if *len == hash.len() {
+                    ThreatMatch::Full
+                } else {
+                    ThreatMatch::Prefix
+                }
+            })
+            .max()
+    }
+
+    fn len(&self) -> usize {
+        self.by_len.values().map(HashSet::len).sum()
+    }
+
+    /// Reads either a Safe Browsing v4 `threatListUpdates:fetch` response (JSON with raw,
+    /// uncompressed hashes) or a plain list of hex encoded hashes or hash prefixes, one per
+    /// line with `#` comments
+    fn parse(contents: &str) -> Result<Self, String> {
+        if contents.trim_start().starts_with('{') {
+            Self::parse_update(contents)
+        } else {
+            Ok(Self::parse_plain(contents))
+        }
+    }
+
+    fn parse_update(contents: &str) -> Result<Self, String> {
+        let response: FetchResponse =
+            serde_json::from_str(contents).map_err(|e| format!("invalid update: {e}"))?;
+
+        let mut prefixes = Self::default();
+        for update in response.list_update_responses {
+            if update.response_type.as_deref() == Some("PARTIAL_UPDATE") {
+                warn!("Threat list contains a partial update, its removals are not applied");
+            }
+            for addition in update.additions {
+                let Some(raw) = addition.raw_hashes else {
+                    warn!("Skipping threat list addition without raw hashes");
+                    continue;
+                };
+                let bytes = STANDARD
+                    .decode(&raw.raw_hashes)
+                    .map_err(|e| format!("invalid rawHashes: {e}"))?;
+                if !(4..=32).contains(&raw.prefix_size) {
+                    return Err(format!("invalid prefixSize {}", raw.prefix_size));
+                }
+                for prefix in bytes.chunks_exact(raw.prefix_size) {
+                    prefixes.insert(prefix);
+                }
+            }
+        }
+        Ok(prefixes)
+    }
+
+    fn parse_plain(contents: &str) -> Self {
+        let mut prefixes = Self::default();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match hex::decode(line) {
+                Ok(prefix) if prefixes.insert(&prefix) => {}
+                _ => warn!("Ignoring invalid threat list entry: {}", line),
+            }
+        }
+        prefixes
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FetchResponse {
+    #[serde(default)]
+    list_update_responses: Vec<ListUpdateResponse>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListUpdateResponse {
+    response_type: Option<String>,
+    #[serde(default)]
+    additions: Vec<ThreatEntrySet>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ThreatEntrySet {
+    raw_hashes: Option<RawHashes>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawHashes {
+    prefix_size: usize,
+    raw_hashes: String,
+}
+
+/// Checks destinations against a locally stored hash prefix threat list. Lookups never
+/// touch the network, so a prefix match can't be confirmed against the full hash and only
+/// flags a link for review, while a full hash match disables it.
+#[derive(Clone, Default)]
+pub struct ThreatList {
+    prefixes: Option<WatchedFile<HashPrefixes>>,
+}
+
+impl ThreatList {
+    /// Loads the list at `path`. A missing or unreadable file is logged and leaves every
+    /// URL unlisted until the background task picks up a valid file.
+    pub fn open(path: &Path) -> Self {
+        Self {
+            prefixes: Some(WatchedFile::open(path, read_list)),
+        }
+    }
+
+    /// The closest match of any Safe Browsing lookup expression of the URL
+    pub fn check(&self, url: &str) -> Option<ThreatMatch> {
+        let url = Url::parse(url).ok()?;
+        self.prefixes.as_ref()?.read(|prefixes| {
+            expressions(&url)
+                .iter()
+                .filter_map(|expression| prefixes.find(&Sha256::digest(expression.as_bytes())))
+                .max()
+        })?
+    }
+
+    /// Scans existing links at startup, whenever the list file changes and at least hourly.
+    /// Links matching a full hash are disabled, links matching only a prefix are flagged
+    /// and queued for review once.
+    pub fn start_scan_task(&self, pg_pool: PgPool, redis_pool: RedisPool) {
+        let Some(prefixes) = self.prefixes.clone() else {
+            return;
+        };
+        prefixes.start_reload_task();
+
+        let threats = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if prefixes.is_loaded() {
+                    match threats.scan(&pg_pool, &redis_pool).await {
+                        Ok((disabled, flagged)) => info!(
+                            "Threat list scan disabled {} and flagged {} links",
+                            disabled, flagged
+                        ),
+                        Err(e) => error!("Error scanning links against threat list: {}", e),
+                    }
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(SCAN_INTERVAL) => {}
+                    _ = prefixes.reloaded() => {}
+                }
+            }
+        });
+    }
+
+    /// Checks every destination of links that aren't disabled by the list yet, including
+    /// rule, variant and schedule URLs, returning how many links were disabled and flagged
+    async fn scan(
+        &self,
+        pg_pool: &PgPool,
+        redis_pool: &RedisPool,
+    ) -> Result<(usize, usize), sqlx::Error> {
+        let (mut disabled, mut flagged) = (0, 0);
+        let mut after = String::new();
+        loop {
+            let batch = urls::list_destinations(pg_pool, &after, SCAN_BATCH_SIZE).await?;
+            let Some((last, _)) = batch.last() else {
+                break;
+            };
+            after = last.clone();
+
+            let mut listed = Vec::new();
+            let mut suspected = Vec::new();
+            for (code, destinations) in batch {
+                match destinations.iter().filter_map(|url| self.check(url)).max() {
+                    Some(ThreatMatch::Full) => listed.push(code),
+                    Some(ThreatMatch::Prefix) => suspected.push(code),
+                    None => {}
+                }
+            }
+
+            if !listed.is_empty() {
+                for code in urls::flag_threats(pg_pool, &listed).await? {
+                    warn!(%code, "Disabled link with a destination on the threat list");
+                    remove_from_cache(redis_pool, &code).await;
+                    disabled += 1;
+                }
+            }
+            if !suspected.is_empty() {
+                for code in urls::flag_threat_reviews(pg_pool, &suspected).await? {
+                    warn!(%code, "Flagged link with a destination matching a threat list prefix");
+                    remove_from_cache(redis_pool, &code).await;
+                    flagged += 1;
+                }
+            }
+        }
+        Ok((disabled, flagged))
+    }
+}
+
+/// The host suffix and path prefix combinations Safe Browsing hashes for a URL, see
+/// https://developers.google.com/safe-browsing/v4/urls-hashing
+fn expressions(url: &Url) -> Vec<String> {
+    let hosts = match url.host() {
+        Some(Host::Domain(domain)) => host_suffixes(domain),
+        Some(ip) => vec![ip.to_string()],
+        None => return Vec::new(),
+    };
+    let paths = path_prefixes(&canonical_path(url.path()), url.query());
+
+    hosts
+        .iter()
+        .flat_map(|host| paths.iter().map(move |path| format!("{host}{path}")))
+        .collect()
+}
+
+/// The exact host plus up to four suffixes formed from its last five components, skipping
+/// the top level domain
+fn host_suffixes(domain: &str) -> Vec<String> {
+    let components: Vec<&str> = domain.split('.').filter(|c| !c.is_empty()).collect();
+    let mut hosts = vec![components.join(".")];
+    let start = components.len().saturating_sub(5).max(1);
+    for i in start..components.len().saturating_sub(1) {
+        hosts.push(components[i..].join("."));
+    }
+    hosts
+}
+
+/// The exact path with and without the query, then up to four directory prefixes from the root
+fn path_prefixes(path: &str, query: Option<&str>) -> Vec<String> {
+    let mut paths = Vec::new();
+    if let Some(query) = query {
+        paths.push(format!("{path}?{query}"));
+    }
+    paths.push(path.to_string());
+
+    let directories = path
+        .rsplit_once('/')
+        .map_or("", |(directories, _)| directories);
+    let mut prefix = "/".to_string();
+    let mut prefixes = vec![prefix.clone()];
+    for segment in directories.split('/').filter(|s| !s.is_empty()).take(3) {
+        prefix.push_str(segment);
+        prefix.push('/');
+        prefixes.push(prefix.clone());
+    }
+
+    for prefix in prefixes {
+        if !paths.contains(&prefix) {
+            paths.push(prefix);
+        }
+    }
+    paths
+}
+
+/// Fully unescapes the path, collapses repeated slashes and escapes control characters,
+/// non-ASCII bytes, `#` and `%` again. Dot segments were already resolved by the url crate.
+fn canonical_path(path: &str) -> String {
+    let mut bytes = path.as_bytes().to_vec();
+    loop {
+        let decoded = percent_decode(&bytes);
+        if decoded == bytes {
+            break;
+        }
+        bytes = decoded;
+    }
+
+    let mut canonical = String::with_capacity(bytes.len());
+    let mut previous = 0;
+    for byte in bytes {
+        if byte == b'/' && previous == b'/' {
+            continue;
+        }
+        if byte <= 0x20 || byte >= 0x7f || byte == b'#' || byte == b'%' {
+            canonical.push_str(&format!("%{byte:02X}"));
+        } else {
+            canonical.push(byte as char);
+        }
+        previous = byte;
+    }
+    canonical
+}
+
+fn percent_decode(bytes: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = bytes.get(i + 1..i + 3)
+            && hex.iter().all(u8::is_ascii_hexdigit)
+        {
+            decoded.push(hex_value(hex[0]) << 4 | hex_value(hex[1]));
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    decoded
+}
+
+fn hex_value(digit: u8) -> u8 {
+    (digit as char).to_digit(16).unwrap_or(0) as u8
+}
+
+fn read_list(path: &Path) -> Option<HashPrefixes> {
+    let parsed = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| HashPrefixes::parse(&contents));
+    match parsed {
+        Ok(prefixes) => {
+            info!(
+                "Loaded {} threat hash prefixes from {}",
+                prefixes.len(),
+                path.display()
+            );
+            Some(prefixes)
+        }
+        Err(e) => {
+            warn!("Failed to load threat list {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    fn sorted_expressions(url: &str) -> Vec<String> {
+        let mut expressions = expressions(&Url::parse(url).unwrap());
+        expressions.sort();
+        expressions
+    }
+
+    fn sorted(expected: &[&str]) -> Vec<String> {
+        let mut expected: Vec<String> = expected.iter().map(|e| e.to_string()).collect();
+        expected.sort();
+        expected
+    }
+
+    #[test]
+    fn expressions_combine_host_suffixes_and_path_prefixes() {
+        assert_eq!(
+            sorted_expressions("http://a.b.c/1/2.html?param=1"),
+            sorted(&[
+                "a.b.c/1/2.html?param=1",
+                "a.b.c/1/2.html",
+                "a.b.c/",
+                "a.b.c/1/",
+                "b.c/1/2.html?param=1",
+                "b.c/1/2.html",
+                "b.c/",
+                "b.c/1/",
+            ])
+        );
+    }
+
+    #[test]
+    fn expressions_use_at_most_five_host_components() {
+        assert_eq!(
+            sorted_expressions("http://a.b.c.d.e.f.g/1.html"),
+            sorted(&[
+                "a.b.c.d.e.f.g/1.html",
+                "a.b.c.d.e.f.g/",
+                "c.d.e.f.g/1.html",
+                "c.d.e.f.g/",
+                "d.e.f.g/1.html",
+                "d.e.f.g/",
+                "e.f.g/1.html",
+                "e.f.g/",
+                "f.g/1.html",
+                "f.g/",
+            ])
+        );
+    }
+
+    #[test]
+    fn expressions_keep_ip_hosts_whole() {
+        assert_eq!(
+            sorted_expressions("http://1.2.3.4/1/"),
+            sorted(&["1.2.3.4/1/", "1.2.3.4/"])
+        );
+    }
+
+    #[test]
+    fn expressions_limit_path_prefixes_to_four() {
+        assert_eq!(
+            sorted_expressions("http://a.b/1/2/3/4/5/6.html"),
+            sorted(&[
+                "a.b/1/2/3/4/5/6.html",
+                "a.b/",
+                "a.b/1/",
+                "a.b/1/2/",
+                "a.b/1/2/3/",
+            ])
+        );
+    }
+
+    #[test]
+    fn canonical_path_unescapes_repeatedly_and_collapses_slashes() {
+        assert_eq!(canonical_path("/%25%32%35"), "/%25");
+        assert_eq!(canonical_path("/a//b"), "/a/b");
+        assert_eq!(canonical_path("/%41%20b"), "/A%20b");
+    }
+
+    #[test]
+    fn plain_list_distinguishes_full_and_prefix_matches() {
+        let threats = ThreatList::open(&fixture("threat_list.txt"));
+        assert_eq!(
+            threats.check("https://evil.example/any/page"),
+            Some(ThreatMatch::Full)
+        );
+        assert_eq!(
+            threats.check("https://www.phish.example/login/form.html"),
+            Some(ThreatMatch::Prefix)
+        );
+        assert_eq!(threats.check("https://phish.example/"), None);
+        assert_eq!(threats.check("https://example.com/"), None);
+    }
+
+    #[test]
+    fn update_response_distinguishes_full_and_prefix_matches() {
+        let threats = ThreatList::open(&fixture("threat_update.json"));
+        assert_eq!(
+            threats.check("http://evil.example/"),
+            Some(ThreatMatch::Full)
+        );
+        assert_eq!(
+            threats.check("http://malware.example/download.exe"),
+            Some(ThreatMatch::Prefix)
+        );
+        assert_eq!(
+            threats.check("http://phish.example/login/"),
+            Some(ThreatMatch::Prefix)
+        );
+        assert_eq!(threats.check("http://example.com/"), None);
+    }
+
+    #[test]
+    fn unloaded_list_matches_nothing() {
+        let threats = ThreatList::open(&fixture("missing.txt"));
+        assert_eq!(threats.check("https://evil.example/"), None);
+        assert_eq!(ThreatList::default().check("https://evil.example/"), None);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::links::{query_key, raw_pairs};
+use serde::{Deserialize, Serialize};
+use url::{Url, form_urlencoded};
+use utoipa::ToSchema;
+
+/// UTM tracking parameters appended to a destination URL when it is shortened
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct UtmParams {
+    #[schema(example = "newsletter")]
+    pub source: Option<String>,
+    #[schema(example = "email")]
+    pub medium: Option<String>,
+    #[schema(example = "spring_sale")]
+    pub campaign: Option<String>,
+    pub term: Option<String>,
+    pub content: Option<String>,
+}
+
+impl UtmParams {
+    /// Fills any field not set on `self` from `fallback`
+    pub fn or(self, fallback: UtmParams) -> Self {
+        Self {
+            source: self.source.or(fallback.source),
+            medium: self.medium.or(fallback.medium),
+            campaign: self.campaign.or(fallback.campaign),
+            term: self.term.or(fallback.term),
+            content: self.content.or(fallback.content),
+        }
+    }
+
+    /// The parameters that are set, as query keys and trimmed values
+    pub(crate) fn pairs(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            ("utm_source", &self.source),
+            ("utm_medium", &self.medium),
+            ("utm_campaign", &self.campaign),
+            ("utm_term", &self.term),
+            ("utm_content", &self.content),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(|v| (key, v))
+        })
+    }
+
+    /// Sets the parameters on the URL, replacing any existing values for the same keys.
+    /// Other parameters are left exactly as they were encoded.
+    pub fn apply(&self, url: &str) -> Result<String, url::ParseError> {
+        let mut parsed = Url::parse(url)?;
+        let params: Vec<(&str, &str)> = self.pairs().collect();
+
+        if params.is_empty() {
+            return Ok(url.to_string());
+        }
+
+        let mut query: Vec<String> = raw_pairs(parsed.query().unwrap_or_default())
+            .filter(|pair| {
+                let key = query_key(pair);
+                !params.iter().any(|(utm, _)| *utm == key)
+            })
+            .map(str::to_string)
+            .collect();
+        query.push(
+            form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(params)
+                .finish(),
+        );
+
+        parsed.set_query(Some(&query.join("&")));
+        Ok(parsed.into())
+    }
+}
+
+/// Reads the campaign a destination URL is tagged with, whether it came from a template
+/// or was appended by hand
+pub fn campaign_of(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()?
+        .query_pairs()
+        .find(|(key, _)| key == "utm_campaign")
+        .map(|(_, value)| value.into_owned())
+        .filter(|campaign| !campaign.is_empty())
+}
+
+/// A named set of UTM parameters stored server side
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct UtmTemplate {
+    #[schema(example = "newsletter")]
+    pub name: String,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub params: UtmParams,
+}
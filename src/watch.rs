@@ -0,0 +1,93 @@
+//! Data loaded from a local file and reloaded whenever the file changes, so lists and
+//! databases can be swapped out without a restart
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::Notify;
+use tracing::debug;
+
+/// How often a watched file is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The contents of a file, parsed by `load` and shared by all clones. A missing or
+/// unreadable file leaves the previous contents in place, or none before the first valid
+/// file is read.
+pub struct WatchedFile<T> {
+    path: PathBuf,
+    load: fn(&Path) -> Option<T>,
+    value: Arc<RwLock<Option<T>>>,
+    reloaded: Arc<Notify>,
+}
+
+impl<T> Clone for WatchedFile<T> {
+    fn clone(&self) -> Self {
+        Self {
+            path: self.path.clone(),
+            load: self.load,
+            value: self.value.clone(),
+            reloaded: self.reloaded.clone(),
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> WatchedFile<T> {
+    /// Reads the file at `path` once. `load` logs why a file couldn't be used.
+    pub fn open(path: &Path, load: fn(&Path) -> Option<T>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            load,
+            value: Arc::new(RwLock::new(load(path))),
+            reloaded: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Runs `read` on the current contents, or returns `None` if no valid file was loaded yet
+    pub fn read<R>(&self, read: impl FnOnce(&T) -> R) -> Option<R> {
+        // Contents are only ever replaced whole, so a poisoned lock still holds a valid value
+        let guard = self.value.read().unwrap_or_else(PoisonError::into_inner);
+        guard.as_ref().map(read)
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.read(|_| ()).is_some()
+    }
+
+    /// Waits until the reload task picked up a changed file
+    pub async fn reloaded(&self) {
+        self.reloaded.notified().await
+    }
+
+    /// Reloads the contents whenever the file's modification time changes
+    pub fn start_reload_task(&self) {
+        let watched = self.clone();
+        tokio::spawn(async move {
+            let mut loaded_at = modified(&watched.path);
+            loop {
+                tokio::time::sleep(RELOAD_INTERVAL).await;
+
+                let current = modified(&watched.path);
+                if current.is_none() || current == loaded_at {
+                    continue;
+                }
+                loaded_at = current;
+
+                let Some(value) = (watched.load)(&watched.path) else {
+                    continue;
+                };
+                *watched
+                    .value
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner) = Some(value);
+                debug!("Reloaded {}", watched.path.display());
+                watched.reloaded.notify_one();
+            }
+        });
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}